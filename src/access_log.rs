@@ -0,0 +1,226 @@
+use crate::config::Config;
+use crate::metrics::{Metrics, Sample};
+use actix_web::body::{BodySize, MessageBody, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, uri::Uri};
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpRequest};
+use futures::{Async, Future, Poll};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub access: LogFormat,
+    /// Take the client IP from `forwarded` or `x-forwarded-for`.
+    pub trust_forwarded: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+    Off,
+}
+
+/// Time spent waiting for upstream, redirects included, set by `proxy_request`.
+pub struct UpstreamLatency(pub Duration);
+
+/// The `ProxyError::code` a request was answered with.
+pub struct ErrorCode(pub &'static str);
+
+/// What is known about a request before its response body is sent.
+struct Entry {
+    format: LogFormat,
+    started: Instant,
+    client: String,
+    method: String,
+    /// Upstream host, `None` for requests that are not proxied.
+    host: Option<String>,
+    path: String,
+    origin: String,
+    status: u16,
+    upstream: Option<Duration>,
+    error: Option<&'static str>,
+}
+
+/**
+ * Logs each request and records it in `metrics` once its response body is
+ * sent, or dropped when the client goes away.
+ */
+pub fn log_request<S, B>(
+    req: ServiceRequest,
+    srv: &mut S,
+    config: &Config,
+    metrics: &web::Data<Metrics>,
+) -> impl Future<Item = ServiceResponse<LoggedBody<B>>, Error = Error>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let format = config.log.access;
+    let trust_forwarded = config.log.trust_forwarded;
+    let started = Instant::now();
+    let metrics = metrics.clone();
+    metrics.start();
+
+    srv.call(req).then(move |res| match res {
+        Ok(res) => {
+            let entry = Entry::new(
+                res.request(),
+                res.status().as_u16(),
+                format,
+                trust_forwarded,
+                started,
+            );
+            Ok(res.map_body(move |_, body| {
+                ResponseBody::Body(LoggedBody {
+                    body,
+                    bytes: 0,
+                    entry,
+                    metrics,
+                })
+            }))
+        }
+        Err(err) => {
+            metrics.finish(Sample {
+                status: 500,
+                host: None,
+                upstream: None,
+                bytes: 0,
+                error: None,
+            });
+            Err(err)
+        }
+    })
+}
+
+impl Entry {
+    fn new(
+        req: &HttpRequest,
+        status: u16,
+        format: LogFormat,
+        trust_forwarded: bool,
+        started: Instant,
+    ) -> Entry {
+        let (host, path) = match req.path().get(1..).unwrap_or("").parse::<Uri>() {
+            Ok(ref uri) if uri.scheme_str().is_some() && uri.host().is_some() => {
+                (uri.host().map(str::to_string), uri.path().to_string())
+            }
+            _ => (None, req.path().to_string()),
+        };
+        Entry {
+            format,
+            started,
+            client: client_ip(req, trust_forwarded),
+            method: req.method().to_string(),
+            host,
+            path,
+            origin: req
+                .headers()
+                .get(header::ORIGIN)
+                .and_then(|origin| origin.to_str().ok())
+                .unwrap_or("-")
+                .to_string(),
+            status,
+            upstream: req
+                .extensions()
+                .get::<UpstreamLatency>()
+                .map(|latency| latency.0),
+            error: req.extensions().get::<ErrorCode>().map(|code| code.0),
+        }
+    }
+
+    /// Query strings are left out, they may carry credentials.
+    fn write(&self, bytes: u64) {
+        let duration = millis(self.started.elapsed());
+        let upstream = self.upstream.map(millis);
+        match self.format {
+            LogFormat::Text => println!(
+                "{} {} \"{} {}{}\" {} {} {:.1}ms upstream={} origin={} error={}",
+                time::now_utc().rfc3339(),
+                self.client,
+                self.method,
+                self.host.as_deref().unwrap_or_default(),
+                self.path,
+                self.status,
+                bytes,
+                duration,
+                upstream.map_or("-".to_string(), |ms| format!("{:.1}ms", ms)),
+                self.origin,
+                self.error.unwrap_or("-")
+            ),
+            LogFormat::Json => println!(
+                "{}",
+                json!({
+                    "time": time::now_utc().rfc3339().to_string(),
+                    "client": self.client,
+                    "method": self.method,
+                    "host": self.host,
+                    "path": self.path,
+                    "status": self.status,
+                    "bytes": bytes,
+                    "duration_ms": duration,
+                    "upstream_ms": upstream,
+                    "origin": self.origin,
+                    "error": self.error,
+                })
+            ),
+            LogFormat::Off => {}
+        }
+    }
+}
+
+pub fn client_ip(req: &HttpRequest, trust_forwarded: bool) -> String {
+    if trust_forwarded {
+        if let Some(remote) = req.connection_info().remote() {
+            return remote.to_string();
+        }
+    }
+    req.peer_addr()
+        .map_or("-".to_string(), |address| address.ip().to_string())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Counts the bytes sent and writes the log entry when dropped.
+pub struct LoggedBody<B> {
+    body: ResponseBody<B>,
+    bytes: u64,
+    entry: Entry,
+    metrics: web::Data<Metrics>,
+}
+
+impl<B: MessageBody> MessageBody for LoggedBody<B> {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(&mut self) -> Poll<Option<Bytes>, Error> {
+        let chunk = self.body.poll_next()?;
+        if let Async::Ready(Some(ref bytes)) = chunk {
+            self.bytes += bytes.len() as u64;
+        }
+        Ok(chunk)
+    }
+}
+
+impl<B> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        self.entry.write(self.bytes);
+        self.metrics.finish(Sample {
+            status: self.entry.status,
+            host: self.entry.host.as_deref(),
+            upstream: self.entry.upstream,
+            bytes: self.bytes,
+            error: self.entry.error,
+        });
+    }
+}
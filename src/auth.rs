@@ -0,0 +1,171 @@
+use crate::config::Config;
+use crate::cors::AllowedOrigins;
+use crate::targets::{host_matches, HostPattern};
+use crate::ProxyError;
+use actix_web::http::{header, HeaderName};
+use actix_web::HttpRequest;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+/**
+ * Requests must carry one of `keys` in the `header` header or the
+ * `query_param` query parameter. An empty list turns authentication off.
+ */
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKey>,
+    pub header: KeyHeader,
+    /// Empty to only accept the header.
+    pub query_param: String,
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig {
+            keys: Vec::new(),
+            header: KeyHeader(HeaderName::from_static("x-proxy-key")),
+            query_param: "proxy_key".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyHeader(pub HeaderName);
+
+impl TryFrom<String> for KeyHeader {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<KeyHeader, String> {
+        HeaderName::from_bytes(name.to_lowercase().as_bytes())
+            .map(KeyHeader)
+            .map_err(|_| format!("Invalid header name {}", name))
+    }
+}
+
+/// A key, optionally only valid from some origins and for some targets.
+#[derive(Deserialize)]
+#[serde(try_from = "KeyEntry")]
+pub struct ApiKey {
+    key: String,
+    origins: AllowedOrigins,
+    /// Empty allows every target allowed by `targets`.
+    targets: Vec<HostPattern>,
+}
+
+/// `"key"` or `{ key = "key", origins = [...], targets = [...] }`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyEntry {
+    Key(String),
+    Restricted(RestrictedKey),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RestrictedKey {
+    key: String,
+    #[serde(default)]
+    origins: Vec<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+}
+
+impl TryFrom<KeyEntry> for ApiKey {
+    type Error = String;
+
+    fn try_from(entry: KeyEntry) -> Result<ApiKey, String> {
+        let entry = match entry {
+            KeyEntry::Key(key) => RestrictedKey {
+                key,
+                origins: Vec::new(),
+                targets: Vec::new(),
+            },
+            KeyEntry::Restricted(entry) => entry,
+        };
+        if entry.key.is_empty() {
+            return Err("API keys cannot be empty".to_string());
+        }
+        Ok(ApiKey {
+            key: entry.key,
+            origins: AllowedOrigins::try_from(entry.origins)?,
+            targets: entry
+                .targets
+                .iter()
+                .map(|pattern| pattern.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// The key a request carries, from the header or else the query string.
+fn api_key(req: &HttpRequest, config: &Config) -> Option<String> {
+    if let Some(key) = req.headers().get(&config.auth.header.0) {
+        return key.to_str().ok().map(str::to_string);
+    }
+    if config.auth.query_param.is_empty() {
+        return None;
+    }
+    req.query_string()
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == config.auth.query_param => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .map(str::to_string)
+}
+
+/**
+ * Whether a query string pair is the API key, which is not passed on. With
+ * authentication off the parameter belongs to the target.
+ */
+pub fn is_key_param(pair: &str, config: &Config) -> bool {
+    !config.auth.keys.is_empty()
+        && !config.auth.query_param.is_empty()
+        && pair.split('=').next() == Some(config.auth.query_param.as_str())
+}
+
+/**
+ * Checks the request's key and origin, returning the index of the key in
+ * `auth.keys`. `None` when authentication is off.
+ */
+pub fn authenticate(req: &HttpRequest, config: &Config) -> Result<Option<usize>, ProxyError> {
+    if config.auth.keys.is_empty() {
+        return Ok(None);
+    }
+    let given = api_key(req, config).ok_or(ProxyError::Unauthorized)?;
+    let index = config
+        .auth
+        .keys
+        .iter()
+        .position(|key| constant_time_eq(key.key.as_bytes(), given.as_bytes()))
+        .ok_or(ProxyError::Unauthorized)?;
+
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok());
+    match (&config.auth.keys[index].origins, origin) {
+        (AllowedOrigins::Any, _) => Ok(Some(index)),
+        (origins, Some(origin)) if origins.matches(origin) => Ok(Some(index)),
+        _ => Err(ProxyError::KeyNotAllowed),
+    }
+}
+
+/// Whether the key at `index` may be used for `host`.
+pub fn allows_target(index: Option<usize>, host: &str, config: &Config) -> bool {
+    match index.map(|index| &config.auth.keys[index].targets) {
+        Some(targets) if !targets.is_empty() => host_matches(targets, host),
+        _ => true,
+    }
+}
+
+/// Compares without returning early, so response times do not leak the key.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,838 @@
+use crate::config::{Config, CookiePolicy};
+use crate::disk_cache::{self, Files, Meta, Writer};
+use actix_web::client::ClientResponse;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue, HttpDate};
+use actix_web::http::{uri::Uri, Method, StatusCode};
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::HttpRequest;
+use futures::{future, Async, Future, Poll, Stream};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Response header telling whether the response came from the cache.
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// Statuses that may be cached, RFC 7231 section 6.1.
+const CACHEABLE_STATUSES: &[u16] = &[200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+/**
+ * A cache shared by all clients in front of upstream, following the
+ * shared cache rules of RFC 7234.
+ */
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Bytes of responses kept, 0 turns the cache off.
+    pub max_size: u64,
+    /// Larger responses are passed on without being cached.
+    pub max_entry_size: u64,
+    /// Keeps responses on disk across restarts instead of in memory.
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            max_size: 0,
+            max_entry_size: 1024 * 1024,
+            dir: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Cache {
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Variants of each URL, one per set of `vary` header values.
+    by_url: HashMap<String, Vec<Slot>>,
+    /// Last use of each entry, oldest first, to its URL and id.
+    lru: BTreeMap<u64, (String, u64)>,
+    size: u64,
+    clock: u64,
+}
+
+struct Slot {
+    id: u64,
+    used: u64,
+    entry: Arc<Entry>,
+}
+
+/// A stored response, with `age` and `set-cookie` left out.
+pub struct Entry {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Body,
+    /// The request header values the response varies on.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    stored: SystemTime,
+    /// Age when stored, from upstream's `age` header.
+    initial_age: Duration,
+    lifetime: Duration,
+}
+
+#[derive(Clone)]
+pub enum Body {
+    Memory(Bytes),
+    Disk(Files),
+}
+
+pub enum Lookup {
+    /// The cache is off or the request cannot be answered from it.
+    Bypass,
+    Miss,
+    /// Stored, but has to be revalidated with upstream before use.
+    Stale(Arc<Entry>),
+    Fresh(Arc<Entry>),
+}
+
+impl Cache {
+    /// Loads the responses kept in `cache.dir`.
+    pub fn open(config: &CacheConfig) -> io::Result<Cache> {
+        let cache = Cache::default();
+        let dir = match &config.dir {
+            Some(dir) if config.max_size > 0 => dir,
+            _ => return Ok(cache),
+        };
+        let mut entries = cache.entries.lock().unwrap_or_else(|err| err.into_inner());
+        for (files, meta) in disk_cache::load(dir)? {
+            match Entry::from_meta(&files, &meta) {
+                Some(entry) => entries.insert(meta.url, Arc::new(entry)),
+                None => files.remove(),
+            }
+        }
+        while entries.size > config.max_size {
+            entries.evict_oldest();
+        }
+        drop(entries);
+        Ok(cache)
+    }
+
+    /// Finds the stored response for a request to `uri`.
+    pub fn lookup(&self, req: &HttpRequest, uri: &Uri, config: &Config) -> Lookup {
+        if config.cache.max_size == 0 || !is_cacheable_request(req, config) {
+            return Lookup::Bypass;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        let entry = match entries.find(&uri.to_string(), req) {
+            Some(entry) => entry,
+            None => return Lookup::Miss,
+        };
+        if entry.is_fresh() && !wants_revalidation(req) {
+            Lookup::Fresh(entry)
+        } else if entry.has_validators() {
+            Lookup::Stale(entry)
+        } else {
+            Lookup::Miss
+        }
+    }
+
+    /**
+     * Updates a stale entry from a `304 Not Modified` response, returning
+     * the entry to answer with.
+     */
+    pub fn refresh(
+        &self,
+        stale: &Entry,
+        req: &HttpRequest,
+        uri: &Uri,
+        not_modified: &HeaderMap,
+        config: &Config,
+    ) -> Arc<Entry> {
+        let mut headers = copy(&stale.headers);
+        for name in not_modified.keys() {
+            if name != header::CONTENT_LENGTH {
+                headers.remove(name);
+            }
+        }
+        for (name, value) in not_modified {
+            if name != header::CONTENT_LENGTH {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        match Entry::new(stale.status, headers, stale.body.clone(), req) {
+            Some(entry) => self.insert(uri, entry, config),
+            None => Arc::new(Entry {
+                status: stale.status,
+                headers: copy(&stale.headers),
+                body: stale.body.clone(),
+                vary: stale.vary.clone(),
+                stored: SystemTime::now(),
+                initial_age: Duration::from_secs(0),
+                lifetime: Duration::from_secs(0),
+            }),
+        }
+    }
+
+    /**
+     * Passes an upstream response body on, storing the response once it is
+     * complete when it may be cached.
+     */
+    pub fn record(
+        cache: web::Data<Cache>,
+        lookup: &Lookup,
+        req: &HttpRequest,
+        uri: &Uri,
+        response: ClientResponse,
+        config: web::Data<Config>,
+    ) -> Recording<ClientResponse> {
+        let (status, headers) = (response.status(), response.headers());
+        let storable = match lookup {
+            Lookup::Bypass => false,
+            _ => is_storable(status, headers, &config),
+        };
+        let sink = match &config.cache.dir {
+            _ if !storable => None,
+            Some(dir) => Some(Sink::Disk(Writer::create(dir))),
+            None => Some(Sink::Memory(BytesMut::new())),
+        };
+        let pending = sink.map(|sink| {
+            let recorded = Recorded {
+                uri: uri.clone(),
+                status,
+                headers: copy(headers),
+                req: req.clone(),
+            };
+            (recorded, sink)
+        });
+        Recording {
+            body: response,
+            cache,
+            pending,
+            finishing: None,
+            config,
+        }
+    }
+
+    /// Stores a response once its body is complete.
+    fn store(&self, recorded: Recorded, body: Body, config: &Config) {
+        match Entry::new(
+            recorded.status,
+            recorded.headers,
+            body.clone(),
+            &recorded.req,
+        ) {
+            Some(entry) => {
+                self.insert(&recorded.uri, entry, config);
+            }
+            None => body.discard(),
+        }
+    }
+
+    fn insert(&self, uri: &Uri, entry: Entry, config: &Config) -> Arc<Entry> {
+        let entry = Arc::new(entry);
+        let size = entry.size();
+        if size > config.cache.max_entry_size || size > config.cache.max_size {
+            entry.body.discard();
+            return entry;
+        }
+        if let Body::Disk(files) = &entry.body {
+            let saved = entry
+                .meta(uri)
+                .ok_or_else(|| io::Error::other("header is not text"))
+                .and_then(|meta| files.save(&meta));
+            if saved.is_err() {
+                files.remove();
+                return entry;
+            }
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.insert(uri.to_string(), entry.clone());
+        while entries.size > config.cache.max_size {
+            entries.evict_oldest();
+        }
+        entry
+    }
+}
+
+impl Entries {
+    fn find(&mut self, url: &str, req: &HttpRequest) -> Option<Arc<Entry>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = self
+            .by_url
+            .get_mut(url)?
+            .iter_mut()
+            .find(|slot| slot.entry.matches(req))?;
+        self.lru.remove(&slot.used);
+        self.lru.insert(clock, (url.to_string(), slot.id));
+        slot.used = clock;
+        Some(slot.entry.clone())
+    }
+
+    /// Replaces any variant for the same `vary` header values.
+    fn insert(&mut self, url: String, entry: Arc<Entry>) {
+        self.clock += 1;
+        let clock = self.clock;
+        let slots = self.by_url.entry(url.clone()).or_default();
+        if let Some(index) = slots.iter().position(|slot| slot.entry.vary == entry.vary) {
+            let old = slots.remove(index);
+            self.lru.remove(&old.used);
+            self.size -= old.entry.size();
+            // A revalidated response keeps its body file.
+            if !old.entry.body.is_same(&entry.body) {
+                old.entry.body.discard();
+            }
+        }
+        self.size += entry.size();
+        slots.push(Slot {
+            id: clock,
+            used: clock,
+            entry,
+        });
+        self.lru.insert(clock, (url, clock));
+    }
+
+    fn evict_oldest(&mut self) {
+        let (url, id) = match self.lru.pop_first() {
+            Some((_, oldest)) => oldest,
+            None => return,
+        };
+        if let Some(slots) = self.by_url.get_mut(&url) {
+            if let Some(index) = slots.iter().position(|slot| slot.id == id) {
+                let old = slots.remove(index);
+                self.size -= old.entry.size();
+                old.entry.body.discard();
+            }
+            if slots.is_empty() {
+                self.by_url.remove(&url);
+            }
+        }
+    }
+}
+
+impl Entry {
+    /// `None` when the response must not be stored.
+    fn new(
+        status: StatusCode,
+        mut headers: HeaderMap,
+        body: Body,
+        req: &HttpRequest,
+    ) -> Option<Entry> {
+        let lifetime = lifetime(&headers)?;
+        let initial_age = headers
+            .get(header::AGE)
+            .and_then(|age| age.to_str().ok())
+            .and_then(|age| age.parse().ok())
+            .map_or(Duration::from_secs(0), Duration::from_secs);
+        headers.remove(header::AGE);
+        headers.remove(header::SET_COOKIE);
+        let vary = vary(&headers)
+            .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+            .map(|name| {
+                let value = req.headers().get(&name).cloned();
+                (name, value)
+            })
+            .collect();
+        Some(Entry {
+            status,
+            headers,
+            body,
+            vary,
+            stored: SystemTime::now(),
+            initial_age,
+            lifetime,
+        })
+    }
+
+    fn from_meta(files: &Files, meta: &Meta) -> Option<Entry> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &meta.headers {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            );
+        }
+        let mut vary = Vec::new();
+        for (name, value) in &meta.vary {
+            let value = match value {
+                Some(value) => Some(HeaderValue::from_str(value).ok()?),
+                None => None,
+            };
+            vary.push((HeaderName::from_bytes(name.as_bytes()).ok()?, value));
+        }
+        Some(Entry {
+            status: StatusCode::from_u16(meta.status).ok()?,
+            headers,
+            body: Body::Disk(files.clone()),
+            vary,
+            stored: UNIX_EPOCH + Duration::from_secs(meta.stored),
+            initial_age: Duration::from_secs(meta.initial_age),
+            lifetime: Duration::from_secs(meta.lifetime),
+        })
+    }
+
+    /// `None` when a header value is not text and cannot be written out.
+    fn meta(&self, uri: &Uri) -> Option<Meta> {
+        let text = |value: &HeaderValue| value.to_str().ok().map(str::to_string);
+        let mut headers = Vec::new();
+        for (name, value) in &self.headers {
+            headers.push((name.to_string(), text(value)?));
+        }
+        let mut vary = Vec::new();
+        for (name, value) in &self.vary {
+            let value = match value {
+                Some(value) => Some(text(value)?),
+                None => None,
+            };
+            vary.push((name.to_string(), value));
+        }
+        Some(Meta {
+            url: uri.to_string(),
+            status: self.status.as_u16(),
+            headers,
+            vary,
+            stored: self
+                .stored
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            initial_age: self.initial_age.as_secs(),
+            lifetime: self.lifetime.as_secs(),
+        })
+    }
+
+    fn current_age(&self) -> Duration {
+        self.initial_age + self.stored.elapsed().unwrap_or_default()
+    }
+
+    /// Seconds since upstream generated the response, for the `age` header.
+    pub fn age(&self) -> u64 {
+        self.current_age().as_secs()
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.current_age() < self.lifetime
+    }
+
+    fn has_validators(&self) -> bool {
+        self.headers.contains_key(header::ETAG) || self.headers.contains_key(header::LAST_MODIFIED)
+    }
+
+    fn matches(&self, req: &HttpRequest) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| req.headers().get(name) == value.as_ref())
+    }
+
+    /// Headers revalidating the entry with upstream.
+    pub fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(modified) = self.headers.get(header::LAST_MODIFIED) {
+            headers.insert(header::IF_MODIFIED_SINCE, modified.clone());
+        }
+        headers
+    }
+
+    /// Whether the client already has this response, by its `if-none-match`.
+    pub fn is_not_modified_for(&self, req: &HttpRequest) -> bool {
+        let etag = match self.headers.get(header::ETAG) {
+            Some(etag) => etag.to_str().unwrap_or_default(),
+            None => return false,
+        };
+        let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        req.headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|tags| tags.to_str().ok())
+            .is_some_and(|tags| {
+                tags.split(',')
+                    .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+            })
+    }
+
+    fn size(&self) -> u64 {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.body.len() + headers as u64
+    }
+}
+
+impl Body {
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Memory(bytes) => bytes.len() as u64,
+            Body::Disk(files) => files.len,
+        }
+    }
+
+    fn is_same(&self, other: &Body) -> bool {
+        match (self, other) {
+            (Body::Disk(files), Body::Disk(other)) => files == other,
+            _ => false,
+        }
+    }
+
+    /// Removes the files of a body that is no longer stored.
+    fn discard(&self) {
+        if let Body::Disk(files) = self {
+            files.remove();
+        }
+    }
+}
+
+/// Stores a response once its body has been passed on completely.
+pub struct Recording<S> {
+    body: S,
+    cache: web::Data<Cache>,
+    /// `None` when the response is not stored.
+    pending: Option<(Recorded, Sink)>,
+    /// The complete body, until the disk is done with it.
+    finishing: Option<(Recorded, Box<dyn Future<Item = Body, Error = io::Error>>)>,
+    config: web::Data<Config>,
+}
+
+/// What is stored with the body.
+struct Recorded {
+    uri: Uri,
+    status: StatusCode,
+    headers: HeaderMap,
+    req: HttpRequest,
+}
+
+/// Where a body being recorded goes.
+enum Sink {
+    Memory(BytesMut),
+    Disk(Writer),
+}
+
+impl Sink {
+    fn len(&self) -> u64 {
+        match self {
+            Sink::Memory(bytes) => bytes.len() as u64,
+            Sink::Disk(writer) => writer.len(),
+        }
+    }
+
+    fn poll_ready(&mut self) -> Poll<(), io::Error> {
+        match self {
+            Sink::Memory(_) => Ok(Async::Ready(())),
+            Sink::Disk(writer) => writer.poll_ready(),
+        }
+    }
+
+    fn write(&mut self, chunk: &Bytes) {
+        match self {
+            Sink::Memory(bytes) => bytes.extend_from_slice(chunk),
+            Sink::Disk(writer) => writer.write(chunk.clone()),
+        }
+    }
+
+    fn finish(self) -> Box<dyn Future<Item = Body, Error = io::Error>> {
+        match self {
+            Sink::Memory(bytes) => Box::new(future::ok(Body::Memory(bytes.freeze()))),
+            Sink::Disk(writer) => Box::new(writer.finish().map(Body::Disk)),
+        }
+    }
+}
+
+impl<S> Stream for Recording<S>
+where
+    S: Stream<Item = Bytes>,
+{
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, S::Error> {
+        if let Some((_, finishing)) = &mut self.finishing {
+            let finished = finishing.poll();
+            if let Ok(Async::NotReady) = finished {
+                return Ok(Async::NotReady);
+            }
+            if let (Some((recorded, _)), Ok(Async::Ready(body))) = (self.finishing.take(), finished)
+            {
+                self.cache.store(recorded, body, &self.config);
+            }
+            return Ok(Async::Ready(None));
+        }
+        // Upstream is read no faster than the disk writes, instead of the
+        // body piling up in memory.
+        if let Some((_, sink)) = &mut self.pending {
+            match sink.poll_ready() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(_) => self.pending = None,
+            }
+        }
+
+        let chunk = match self.body.poll() {
+            Ok(Async::Ready(chunk)) => chunk,
+            other => {
+                if other.is_err() {
+                    self.pending = None;
+                }
+                return other;
+            }
+        };
+        let max_size = self.config.cache.max_entry_size;
+        // Responses growing past `max_entry_size` are passed on but not stored.
+        match (&chunk, self.pending.take()) {
+            (Some(chunk), Some((recorded, mut sink)))
+                if sink.len() + chunk.len() as u64 <= max_size =>
+            {
+                sink.write(chunk);
+                self.pending = Some((recorded, sink));
+            }
+            (None, Some((recorded, sink))) => {
+                self.finishing = Some((recorded, sink.finish()));
+                return self.poll();
+            }
+            _ => {}
+        }
+        Ok(Async::Ready(chunk))
+    }
+}
+
+/// Forwarded cookies may make the response personal, like `authorization`.
+fn is_cacheable_request(req: &HttpRequest, config: &Config) -> bool {
+    req.method() == Method::GET
+        && !req.headers().contains_key(header::AUTHORIZATION)
+        && (config.headers.cookies != CookiePolicy::Forward
+            || !req.headers().contains_key(header::COOKIE))
+        && !directives(req.headers()).any(|(name, _)| name == "no-store")
+}
+
+/// Whether the client asked for a response validated with upstream.
+fn wants_revalidation(req: &HttpRequest) -> bool {
+    let pragma = req
+        .headers()
+        .get(header::PRAGMA)
+        .and_then(|pragma| pragma.to_str().ok())
+        .is_some_and(|pragma| pragma.to_lowercase().contains("no-cache"));
+    pragma
+        || directives(req.headers()).any(|(name, value)| {
+            name == "no-cache" || (name == "max-age" && value.as_deref() == Some("0"))
+        })
+}
+
+/// Cookies are only returned to the client they were set for.
+fn is_storable(status: StatusCode, headers: &HeaderMap, config: &Config) -> bool {
+    CACHEABLE_STATUSES.contains(&status.as_u16())
+        && (config.headers.cookies == CookiePolicy::Strip
+            || !headers.contains_key(header::SET_COOKIE))
+        && lifetime(headers).is_some()
+}
+
+/**
+ * How long a response is fresh for, from `cache-control` or `expires`.
+ * `None` when it must not be stored, or would always need revalidating
+ * but cannot be revalidated.
+ */
+fn lifetime(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = None;
+    let mut shared_max_age = None;
+    let mut no_cache = false;
+    for (name, value) in directives(headers) {
+        let seconds = || value.as_deref().and_then(|value| value.parse::<u64>().ok());
+        match name.as_str() {
+            "no-store" | "private" => return None,
+            "no-cache" => no_cache = true,
+            "max-age" => max_age = seconds(),
+            "s-maxage" => shared_max_age = seconds(),
+            _ => {}
+        }
+    }
+    if vary(headers).any(|name| name == "*") {
+        return None;
+    }
+
+    let lifetime = if no_cache {
+        Duration::from_secs(0)
+    } else {
+        match shared_max_age.or(max_age) {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => expires(headers).unwrap_or_default(),
+        }
+    };
+    let validated =
+        headers.contains_key(header::ETAG) || headers.contains_key(header::LAST_MODIFIED);
+    if lifetime == Duration::from_secs(0) && !validated {
+        return None;
+    }
+    Some(lifetime)
+}
+
+/// Lifetime from `expires`, relative to upstream's `date`.
+fn expires(headers: &HeaderMap) -> Option<Duration> {
+    let date = |name| -> Option<SystemTime> {
+        let value = headers.get(name)?.to_str().ok()?;
+        value.parse::<HttpDate>().ok().map(SystemTime::from)
+    };
+    let expires = date(header::EXPIRES)?;
+    let now = date(header::DATE).unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(now).unwrap_or_default())
+}
+
+/// `cache-control` directives as lowercase names and unquoted values.
+fn directives(headers: &HeaderMap) -> impl Iterator<Item = (String, Option<String>)> + '_ {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap_or_default().trim().to_lowercase();
+            let value = parts
+                .next()
+                .map(|value| value.trim().trim_matches('"').to_string());
+            (name, value)
+        })
+}
+
+/// `HeaderMap` is not `Clone`.
+fn copy(headers: &HeaderMap) -> HeaderMap {
+    let mut copy = HeaderMap::new();
+    for (name, value) in headers {
+        copy.append(name.clone(), value.clone());
+    }
+    copy
+}
+
+/// Lowercase header names in `vary`, `*` included.
+fn vary(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(header::VARY)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn lifetime_of(pairs: &[(&str, &str)]) -> Option<u64> {
+        lifetime(&headers(pairs)).map(|lifetime| lifetime.as_secs())
+    }
+
+    #[test]
+    fn parses_directives() {
+        let headers = headers(&[
+            ("cache-control", "Max-Age=60, no-cache=\"set-cookie\""),
+            ("cache-control", " public"),
+        ]);
+        let mut parsed = directives(&headers).collect::<Vec<_>>();
+        parsed.sort();
+        assert_eq!(
+            parsed,
+            vec![
+                ("max-age".to_string(), Some("60".to_string())),
+                ("no-cache".to_string(), Some("set-cookie".to_string())),
+                ("public".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn lifetimes() {
+        assert_eq!(lifetime_of(&[("cache-control", "max-age=60")]), Some(60));
+        assert_eq!(
+            lifetime_of(&[("cache-control", "max-age=60, s-maxage=10")]),
+            Some(10)
+        );
+        assert_eq!(
+            lifetime_of(&[
+                ("date", "Sun, 18 Oct 2026 10:00:00 GMT"),
+                ("expires", "Sun, 18 Oct 2026 10:02:00 GMT"),
+            ]),
+            Some(120)
+        );
+        assert_eq!(
+            lifetime_of(&[
+                ("cache-control", "max-age=60"),
+                ("date", "Sun, 18 Oct 2026 10:00:00 GMT"),
+                ("expires", "Sun, 18 Oct 2026 10:02:00 GMT"),
+            ]),
+            Some(60)
+        );
+    }
+
+    #[test]
+    fn unstorable_lifetimes() {
+        assert_eq!(lifetime_of(&[]), None);
+        assert_eq!(
+            lifetime_of(&[("cache-control", "max-age=60, private")]),
+            None
+        );
+        assert_eq!(lifetime_of(&[("cache-control", "no-store")]), None);
+        assert_eq!(
+            lifetime_of(&[("cache-control", "max-age=60"), ("vary", "accept, *")]),
+            None
+        );
+        // Always revalidated, so only kept with a validator.
+        assert_eq!(lifetime_of(&[("cache-control", "no-cache")]), None);
+        assert_eq!(
+            lifetime_of(&[("cache-control", "no-cache, max-age=60"), ("etag", "\"a\"")]),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn matches_vary_headers() {
+        let stored_for = TestRequest::default()
+            .header("accept-encoding", "gzip")
+            .to_http_request();
+        let entry = Entry::new(
+            StatusCode::OK,
+            headers(&[
+                ("cache-control", "max-age=60"),
+                ("vary", "Accept-Encoding, accept-language"),
+            ]),
+            Body::Memory(Bytes::new()),
+            &stored_for,
+        )
+        .unwrap();
+
+        assert!(entry.matches(&stored_for));
+        let other_encoding = TestRequest::default()
+            .header("accept-encoding", "br")
+            .to_http_request();
+        assert!(!entry.matches(&other_encoding));
+        let no_encoding = TestRequest::default().to_http_request();
+        assert!(!entry.matches(&no_encoding));
+        let with_language = TestRequest::default()
+            .header("accept-encoding", "gzip")
+            .header("accept-language", "en")
+            .to_http_request();
+        assert!(!entry.matches(&with_language));
+    }
+
+    #[test]
+    fn bypasses_forwarded_cookies() {
+        let mut config = Config::default();
+        let req = TestRequest::default()
+            .header("cookie", "session=1")
+            .to_http_request();
+        assert!(is_cacheable_request(&req, &config));
+        config.headers.cookies = CookiePolicy::Forward;
+        assert!(!is_cacheable_request(&req, &config));
+        assert!(is_cacheable_request(
+            &TestRequest::default().to_http_request(),
+            &config
+        ));
+    }
+}
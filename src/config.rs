@@ -0,0 +1,600 @@
+use crate::access_log::LogConfig;
+use crate::auth::AuthConfig;
+use crate::cache::CacheConfig;
+use crate::cors::AllowedOrigins;
+use crate::headers::{HeaderList, HeaderPolicy};
+use crate::health::HealthConfig;
+use crate::host_limits::HostLimitsConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::redirects::RedirectsConfig;
+use crate::signing::SigningConfig;
+use crate::targets::TargetRules;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::{env, fmt, fs};
+use toml::value::{Table, Value};
+
+/**
+ * Settings are read from, in order of precedence,
+ * - command line flags, `--port 8080`
+ * - environment variables, `PORT=8080`
+ * - a TOML file given by `--config` or `CONFIG`
+ * - defaults
+ */
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: IpAddr,
+    pub port: u16,
+    pub cors: CorsConfig,
+    pub targets: TargetRules,
+    pub auth: AuthConfig,
+    pub signing: SigningConfig,
+    pub headers: HeadersConfig,
+    pub redirects: RedirectsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub limits: LimitsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub upstream_limits: HostLimitsConfig,
+    pub cache: CacheConfig,
+    pub log: LogConfig,
+    pub health: HealthConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub allow_credentials: bool,
+    /// Seconds browsers may cache preflight responses.
+    pub max_age: u32,
+    /// Returned upstream headers listed in `access-control-expose-headers`.
+    pub expose_headers: HeaderPolicy,
+    /// Listed in `access-control-expose-headers` whether returned or not.
+    pub extra_expose_headers: HeaderList,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeadersConfig {
+    pub forward: HeaderPolicy,
+    pub cookies: CookiePolicy,
+}
+
+/// Upstream timeouts in seconds.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub connect: u64,
+    /// Per upstream request, until the response headers arrive.
+    pub request: u64,
+    /// Per proxied request, redirects included.
+    pub total: u64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Largest request body forwarded upstream in bytes, 0 for no limit.
+    pub max_request_body: u64,
+}
+
+/// What happens to `cookie` and `set-cookie` headers passing the proxy.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CookiePolicy {
+    Forward,
+    #[default]
+    Strip,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: Ipv4Addr::UNSPECIFIED.into(),
+            port: 8080,
+            cors: CorsConfig::default(),
+            targets: TargetRules::default(),
+            auth: AuthConfig::default(),
+            signing: SigningConfig::default(),
+            headers: HeadersConfig::default(),
+            redirects: RedirectsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            limits: LimitsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            upstream_limits: HostLimitsConfig::default(),
+            cache: CacheConfig::default(),
+            log: LogConfig::default(),
+            health: HealthConfig::default(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: AllowedOrigins::Any,
+            allow_credentials: false,
+            max_age: 86400,
+            expose_headers: HeaderPolicy::All,
+            extra_expose_headers: HeaderList::default(),
+        }
+    }
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> TimeoutsConfig {
+        TimeoutsConfig {
+            connect: 5,
+            request: 30,
+            total: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    /// Comma separated on the command line and in the environment.
+    List,
+}
+
+/// A setting that can be overridden by a flag and an environment variable.
+struct Setting {
+    key: &'static str,
+    flag: &'static str,
+    env: &'static str,
+    kind: Kind,
+    help: &'static str,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "bind",
+        flag: "--bind",
+        env: "BIND",
+        kind: Kind::String,
+        help: "Address to listen on [default: 0.0.0.0]",
+    },
+    Setting {
+        key: "port",
+        flag: "--port",
+        env: "PORT",
+        kind: Kind::Integer,
+        help: "Port to listen on [default: 8080]",
+    },
+    Setting {
+        key: "cors.allowed_origins",
+        flag: "--allowed-origins",
+        env: "ALLOWED_ORIGINS",
+        kind: Kind::List,
+        help: "Origins allowed to use the proxy [default: *]",
+    },
+    Setting {
+        key: "cors.allow_credentials",
+        flag: "--allow-credentials",
+        env: "ALLOW_CREDENTIALS",
+        kind: Kind::Boolean,
+        help: "Allow credentialed requests [default: false]",
+    },
+    Setting {
+        key: "cors.max_age",
+        flag: "--cors-max-age",
+        env: "CORS_MAX_AGE",
+        kind: Kind::Integer,
+        help: "Seconds browsers may cache preflights [default: 86400]",
+    },
+    Setting {
+        key: "cors.expose_headers",
+        flag: "--expose-headers",
+        env: "EXPOSE_HEADERS",
+        kind: Kind::List,
+        help: "Upstream headers readable by JavaScript [default: *]",
+    },
+    Setting {
+        key: "cors.extra_expose_headers",
+        flag: "--extra-expose-headers",
+        env: "EXTRA_EXPOSE_HEADERS",
+        kind: Kind::List,
+        help: "Headers always listed as readable by JavaScript",
+    },
+    Setting {
+        key: "targets.allow",
+        flag: "--allowed-targets",
+        env: "ALLOWED_TARGETS",
+        kind: Kind::List,
+        help: "Upstream hosts the proxy may fetch from [default: any]",
+    },
+    Setting {
+        key: "targets.deny",
+        flag: "--denied-targets",
+        env: "DENIED_TARGETS",
+        kind: Kind::List,
+        help: "Upstream hosts the proxy must not fetch from",
+    },
+    Setting {
+        key: "targets.allow_private",
+        flag: "--allow-private-targets",
+        env: "ALLOW_PRIVATE_TARGETS",
+        kind: Kind::Boolean,
+        help: "Allow loopback, private and link-local upstreams [default: false]",
+    },
+    Setting {
+        key: "auth.keys",
+        flag: "--api-keys",
+        env: "API_KEYS",
+        kind: Kind::List,
+        help: "API keys required to use the proxy [default: none, no authentication]",
+    },
+    Setting {
+        key: "auth.header",
+        flag: "--api-key-header",
+        env: "API_KEY_HEADER",
+        kind: Kind::String,
+        help: "Request header carrying the API key [default: x-proxy-key]",
+    },
+    Setting {
+        key: "auth.query_param",
+        flag: "--api-key-query-param",
+        env: "API_KEY_QUERY_PARAM",
+        kind: Kind::String,
+        help: "Query parameter carrying the API key, empty to disable [default: proxy_key]",
+    },
+    Setting {
+        key: "signing.secret",
+        flag: "--signing-secret",
+        env: "SIGNING_SECRET",
+        kind: Kind::String,
+        help: "Only proxy URLs signed with this HMAC secret [default: none]",
+    },
+    Setting {
+        key: "headers.forward",
+        flag: "--forward-headers",
+        env: "FORWARD_HEADERS",
+        kind: Kind::List,
+        help: "Request headers forwarded upstream [default: *]",
+    },
+    Setting {
+        key: "headers.cookies",
+        flag: "--cookies",
+        env: "COOKIES",
+        kind: Kind::String,
+        help: "forward or strip cookies [default: strip]",
+    },
+    Setting {
+        key: "redirects.follow",
+        flag: "--follow-redirects",
+        env: "FOLLOW_REDIRECTS",
+        kind: Kind::Boolean,
+        help: "Follow upstream redirects inside the proxy [default: false]",
+    },
+    Setting {
+        key: "redirects.max",
+        flag: "--max-redirects",
+        env: "MAX_REDIRECTS",
+        kind: Kind::Integer,
+        help: "Redirects followed per request [default: 10]",
+    },
+    Setting {
+        key: "redirects.rewrite_location",
+        flag: "--rewrite-location",
+        env: "REWRITE_LOCATION",
+        kind: Kind::Boolean,
+        help: "Rewrite returned Location headers to go through the proxy [default: false]",
+    },
+    Setting {
+        key: "redirects.public_url",
+        flag: "--public-url",
+        env: "PUBLIC_URL",
+        kind: Kind::String,
+        help: "The proxy's URL used for rewritten Location headers [default: from the request]",
+    },
+    Setting {
+        key: "timeouts.connect",
+        flag: "--connect-timeout",
+        env: "CONNECT_TIMEOUT",
+        kind: Kind::Integer,
+        help: "Seconds to wait for upstream connections [default: 5]",
+    },
+    Setting {
+        key: "timeouts.request",
+        flag: "--request-timeout",
+        env: "REQUEST_TIMEOUT",
+        kind: Kind::Integer,
+        help: "Seconds to wait for upstream response headers [default: 30]",
+    },
+    Setting {
+        key: "timeouts.total",
+        flag: "--total-timeout",
+        env: "TOTAL_TIMEOUT",
+        kind: Kind::Integer,
+        help: "Seconds a proxied request may take, redirects included [default: 60]",
+    },
+    Setting {
+        key: "limits.max_request_body",
+        flag: "--max-request-body",
+        env: "MAX_REQUEST_BODY",
+        kind: Kind::Integer,
+        help: "Largest request body in bytes, 0 for no limit [default: 0]",
+    },
+    Setting {
+        key: "rate_limit.requests",
+        flag: "--rate-limit",
+        env: "RATE_LIMIT",
+        kind: Kind::Integer,
+        help: "Requests per client and period, 0 for no limit [default: 0]",
+    },
+    Setting {
+        key: "rate_limit.period",
+        flag: "--rate-limit-period",
+        env: "RATE_LIMIT_PERIOD",
+        kind: Kind::Integer,
+        help: "Rate limit period in seconds [default: 60]",
+    },
+    Setting {
+        key: "rate_limit.burst",
+        flag: "--rate-limit-burst",
+        env: "RATE_LIMIT_BURST",
+        kind: Kind::Integer,
+        help: "Requests a client may make at once, 0 for the rate limit [default: 0]",
+    },
+    Setting {
+        key: "rate_limit.key",
+        flag: "--rate-limit-key",
+        env: "RATE_LIMIT_KEY",
+        kind: Kind::String,
+        help: "What identifies a client, ip, api_key or origin [default: ip]",
+    },
+    Setting {
+        key: "upstream_limits.max_concurrent",
+        flag: "--upstream-max-concurrent",
+        env: "UPSTREAM_MAX_CONCURRENT",
+        kind: Kind::Integer,
+        help: "Requests in flight per upstream host, 0 for no limit [default: 0]",
+    },
+    Setting {
+        key: "upstream_limits.requests",
+        flag: "--upstream-rate-limit",
+        env: "UPSTREAM_RATE_LIMIT",
+        kind: Kind::Integer,
+        help: "Requests per upstream host and period, 0 for no limit [default: 0]",
+    },
+    Setting {
+        key: "upstream_limits.period",
+        flag: "--upstream-rate-limit-period",
+        env: "UPSTREAM_RATE_LIMIT_PERIOD",
+        kind: Kind::Integer,
+        help: "Upstream rate limit period in seconds [default: 60]",
+    },
+    Setting {
+        key: "upstream_limits.queue_timeout",
+        flag: "--upstream-queue-timeout",
+        env: "UPSTREAM_QUEUE_TIMEOUT",
+        kind: Kind::Integer,
+        help: "Seconds to wait for a busy upstream host, 0 to not wait [default: 0]",
+    },
+    Setting {
+        key: "cache.max_size",
+        flag: "--cache-max-size",
+        env: "CACHE_MAX_SIZE",
+        kind: Kind::Integer,
+        help: "Bytes of upstream responses to cache, 0 for no cache [default: 0]",
+    },
+    Setting {
+        key: "cache.max_entry_size",
+        flag: "--cache-max-entry-size",
+        env: "CACHE_MAX_ENTRY_SIZE",
+        kind: Kind::Integer,
+        help: "Largest response cached in bytes [default: 1048576]",
+    },
+    Setting {
+        key: "cache.dir",
+        flag: "--cache-dir",
+        env: "CACHE_DIR",
+        kind: Kind::String,
+        help: "Directory to keep cached responses in across restarts [default: in memory]",
+    },
+    Setting {
+        key: "log.access",
+        flag: "--access-log",
+        env: "ACCESS_LOG",
+        kind: Kind::String,
+        help: "Access log format, text, json or off [default: text]",
+    },
+    Setting {
+        key: "log.trust_forwarded",
+        flag: "--trust-forwarded",
+        env: "TRUST_FORWARDED",
+        kind: Kind::Boolean,
+        help: "Log the client IP from Forwarded or X-Forwarded-For [default: false]",
+    },
+    Setting {
+        key: "health.upstream",
+        flag: "--ready-upstream",
+        env: "READY_UPSTREAM",
+        kind: Kind::String,
+        help: "URL /readyz checks before reporting ready [default: none]",
+    },
+];
+
+/// Why the configuration could not be loaded.
+pub enum ConfigError {
+    Help,
+    Usage(String),
+    File(String, String),
+    Invalid(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigError::*;
+
+        match self {
+            Help => write!(f, "{}", help()),
+            Usage(reason) => write!(f, "{}\n\n{}", reason, help()),
+            File(path, reason) => write!(f, "Unable to read config file {}: {}", path, reason),
+            Invalid(source, reason) => write!(f, "Invalid {}: {}", source, reason),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Config, ConfigError> {
+        let args = env::args().skip(1).collect::<Vec<_>>();
+        let (config_file, flags) = parse_args(&args)?;
+
+        let mut table = match config_file.or_else(|| env::var("CONFIG").ok()) {
+            Some(path) => read_file(&path)?,
+            None => Table::new(),
+        };
+        for setting in SETTINGS {
+            if let Ok(value) = env::var(setting.env) {
+                let value = parse_value(setting, &value)
+                    .map_err(|reason| ConfigError::Invalid(setting.env.to_string(), reason))?;
+                insert(&mut table, setting.key, value);
+            }
+        }
+        for (setting, value) in flags {
+            let value = parse_value(setting, &value)
+                .map_err(|reason| ConfigError::Invalid(setting.flag.to_string(), reason))?;
+            insert(&mut table, setting.key, value);
+        }
+
+        let config = Value::Table(table)
+            .try_into::<Config>()
+            .map_err(|err| ConfigError::Invalid("configuration".to_string(), err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, reason: &str| {
+            Err(ConfigError::Invalid(key.to_string(), reason.to_string()))
+        };
+
+        if self.port == 0 {
+            return invalid("port", "must be between 1 and 65535");
+        }
+        if self.cors.allow_credentials && matches!(self.cors.allowed_origins, AllowedOrigins::Any) {
+            return invalid(
+                "cors.allow_credentials",
+                "requires cors.allowed_origins to list the allowed origins, not *",
+            );
+        }
+        if self.timeouts.connect == 0 {
+            return invalid("timeouts.connect", "must be at least 1 second");
+        }
+        if self.timeouts.request == 0 {
+            return invalid("timeouts.request", "must be at least 1 second");
+        }
+        if self.timeouts.total == 0 {
+            return invalid("timeouts.total", "must be at least 1 second");
+        }
+        if self.rate_limit.period == 0 {
+            return invalid("rate_limit.period", "must be at least 1 second");
+        }
+        if self.upstream_limits.period == 0 {
+            return invalid("upstream_limits.period", "must be at least 1 second");
+        }
+        Ok(())
+    }
+}
+
+type Flags = Vec<(&'static Setting, String)>;
+
+/// Splits `--config` from setting flags, accepting `--flag value` and `--flag=value`.
+fn parse_args(args: &[String]) -> Result<(Option<String>, Flags), ConfigError> {
+    let mut config_file = None;
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.find('=') {
+            Some(index) => (&arg[..index], Some(arg[index + 1..].to_string())),
+            None => (arg.as_str(), None),
+        };
+        if flag == "--help" || flag == "-h" {
+            return Err(ConfigError::Help);
+        }
+
+        let setting = SETTINGS.iter().find(|setting| setting.flag == flag);
+        if setting.is_none() && flag != "--config" {
+            return Err(ConfigError::Usage(format!("Unknown argument {}", flag)));
+        }
+        let value = match (setting.map(|setting| setting.kind), inline_value) {
+            (_, Some(value)) => value,
+            (Some(Kind::Boolean), None) => "true".to_string(),
+            (_, None) => args
+                .next()
+                .cloned()
+                .ok_or_else(|| ConfigError::Usage(format!("Missing value for {}", flag)))?,
+        };
+        match setting {
+            Some(setting) => flags.push((setting, value)),
+            None => config_file = Some(value),
+        }
+    }
+    Ok((config_file, flags))
+}
+
+fn read_file(path: &str) -> Result<Table, ConfigError> {
+    let error = |reason: String| ConfigError::File(path.to_string(), reason);
+    let contents = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+    toml::from_str::<Table>(&contents).map_err(|err| error(err.to_string()))
+}
+
+fn parse_value(setting: &Setting, value: &str) -> Result<Value, String> {
+    match setting.kind {
+        Kind::String => Ok(Value::String(value.to_string())),
+        Kind::Integer => value
+            .parse::<i64>()
+            .map(Value::Integer)
+            .map_err(|_| format!("expected a number, got {}", value)),
+        Kind::Boolean => value
+            .parse::<bool>()
+            .map(Value::Boolean)
+            .map_err(|_| format!("expected true or false, got {}", value)),
+        Kind::List => Ok(Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        )),
+    }
+}
+
+/// Sets a dotted key like `cors.max_age`, creating tables as needed.
+fn insert(table: &mut Table, key: &str, value: Value) {
+    match key.find('.') {
+        Some(index) => {
+            let section = table
+                .entry(key[..index].to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if let Value::Table(section) = section {
+                insert(section, &key[index + 1..], value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn help() -> String {
+    let mut help = String::from(
+        "Usage: actix-cors [--config FILE] [OPTIONS]\n\n\
+         Options, also read from the environment variable in parentheses:\n    \
+         --config FILE (CONFIG)\n        TOML configuration file\n",
+    );
+    for setting in SETTINGS {
+        help.push_str(&format!(
+            "    {} ({})\n        {}\n",
+            setting.flag, setting.env, setting.help
+        ));
+    }
+    help
+}
@@ -0,0 +1,227 @@
+use crate::config::Config;
+use crate::ProxyError;
+use actix_web::dev::{HttpResponseBuilder, ServiceResponse};
+use actix_web::http::{header, HeaderMap, HeaderName, HeaderValue, Method};
+use actix_web::{HttpRequest, HttpResponse};
+use regex::Regex;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Methods accepted by `is_supported_method`.
+const ALLOW_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Origins allowed to read proxied responses.
+#[derive(Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<OriginPattern>),
+}
+
+pub enum OriginPattern {
+    /// `https://example.com`
+    Exact(String),
+    /// `https://*.example.com`, matches subdomains at any depth
+    Subdomains { scheme: String, domain: String },
+    /// `/^https://(www|app)\.example\.com$/`
+    Regex(Regex),
+}
+
+impl TryFrom<Vec<String>> for AllowedOrigins {
+    type Error = String;
+
+    /// `*` allows any origin, which is also the default when the list is empty.
+    fn try_from(patterns: Vec<String>) -> Result<AllowedOrigins, String> {
+        if patterns.is_empty() || patterns.iter().any(|pattern| pattern == "*") {
+            return Ok(AllowedOrigins::Any);
+        }
+        patterns
+            .iter()
+            .map(|pattern| pattern.parse())
+            .collect::<Result<_, _>>()
+            .map(AllowedOrigins::List)
+    }
+}
+
+impl AllowedOrigins {
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(patterns) => patterns.iter().any(|p| p.matches(origin)),
+        }
+    }
+}
+
+impl OriginPattern {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Exact(allowed) => allowed == origin,
+            OriginPattern::Subdomains { scheme, domain } => {
+                let host = match origin.split("://").collect::<Vec<_>>().as_slice() {
+                    [origin_scheme, host] if origin_scheme == scheme => *host,
+                    _ => return false,
+                };
+                host.len() > domain.len() + 1
+                    && host.ends_with(domain.as_str())
+                    && host[..host.len() - domain.len()].ends_with('.')
+            }
+            OriginPattern::Regex(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<OriginPattern, String> {
+        if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+            let anchored = format!("^(?:{})$", &pattern[1..pattern.len() - 1]);
+            return Regex::new(&anchored)
+                .map(OriginPattern::Regex)
+                .map_err(|err| format!("Invalid origin regex {}: {}", pattern, err));
+        }
+
+        let parts = pattern.split("://").collect::<Vec<_>>();
+        match parts.as_slice() {
+            [scheme, host] if host.starts_with("*.") && host.len() > 2 => {
+                Ok(OriginPattern::Subdomains {
+                    scheme: scheme.to_string(),
+                    domain: host[2..].to_string(),
+                })
+            }
+            [_, host] if !host.is_empty() && !host.contains('*') => Ok(OriginPattern::Exact(
+                pattern.trim_end_matches('/').to_string(),
+            )),
+            _ => Err(format!("Invalid origin {}", pattern)),
+        }
+    }
+}
+
+/**
+ * The `access-control-allow-origin` value for a request. Requests without an
+ * `origin` header are not CORS requests and get no value, while origins not
+ * on the list are rejected. Listed origins are echoed, as browsers refuse
+ * `*` for credentialed requests.
+ */
+pub fn allow_origin(req: &HttpRequest, config: &Config) -> Result<Option<HeaderValue>, ProxyError> {
+    let origin = match req.headers().get(header::ORIGIN) {
+        Some(origin) => origin,
+        None => return Ok(None),
+    };
+    if !echoes_origin(config) {
+        return Ok(Some(HeaderValue::from_static("*")));
+    }
+    match origin.to_str() {
+        Ok(value) if config.cors.allowed_origins.matches(value) => Ok(Some(origin.clone())),
+        _ => Err(ProxyError::OriginNotAllowed),
+    }
+}
+
+/// `Config::validate` refuses credentials for any origin, so only listed origins are echoed.
+fn echoes_origin(config: &Config) -> bool {
+    matches!(config.cors.allowed_origins, AllowedOrigins::List(_))
+}
+
+/**
+ * Adds the origin headers to every response the proxy sends, proxied or
+ * not, so browsers can read errors and the usage page too. Rejected origins
+ * get none.
+ */
+pub fn add_response_origin_headers<B>(res: &mut ServiceResponse<B>, config: &Config) {
+    let allow_origin = allow_origin(res.request(), config).unwrap_or(None);
+    add_origin_headers(res.headers_mut(), allow_origin, config);
+}
+
+/**
+ * Sets `access-control-allow-origin` and, when enabled,
+ * `access-control-allow-credentials`. Echoed origins vary per request, so
+ * caches are told with `vary: origin`, next to any upstream `vary`.
+ */
+fn add_origin_headers(headers: &mut HeaderMap, allow_origin: Option<HeaderValue>, config: &Config) {
+    if echoes_origin(config) {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    if let Some(allow_origin) = allow_origin {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if config.cors.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+/**
+ * Browsers only let JavaScript read these response headers, unless
+ * `access-control-expose-headers` lists others.
+ */
+const SAFELISTED: &[HeaderName] = &[
+    header::CACHE_CONTROL,
+    header::CONTENT_LANGUAGE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::PRAGMA,
+];
+
+/**
+ * Lists the returned upstream headers allowed by `cors.expose_headers`,
+ * followed by `cors.extra_expose_headers`. `set-cookie` is never readable
+ * by JavaScript, so it is left out.
+ */
+pub fn add_expose_headers(
+    result: &mut HttpResponseBuilder,
+    returned: &[HeaderName],
+    config: &Config,
+) {
+    let mut exposed = returned
+        .iter()
+        .filter(|name| {
+            !SAFELISTED.contains(name)
+                && **name != header::SET_COOKIE
+                && config.cors.expose_headers.allows(name)
+        })
+        .map(HeaderName::as_str)
+        .collect::<Vec<_>>();
+    for name in &config.cors.extra_expose_headers.0 {
+        if !exposed.contains(&name.as_str()) {
+            exposed.push(name.as_str());
+        }
+    }
+
+    if !exposed.is_empty() {
+        result.header(header::ACCESS_CONTROL_EXPOSE_HEADERS, exposed.join(", "));
+    }
+}
+
+/**
+ * A preflight is an OPTIONS request carrying `access-control-request-method`.
+ * Plain OPTIONS requests are proxied like any other method.
+ */
+pub fn is_preflight(req: &HttpRequest) -> bool {
+    req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/**
+ * Answers a preflight without contacting upstream. Requested headers are
+ * echoed back as they are.
+ */
+pub fn preflight_response(req: &HttpRequest, config: &Config) -> HttpResponse {
+    let mut result = HttpResponse::NoContent();
+    result
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOW_METHODS)
+        .header(
+            header::ACCESS_CONTROL_MAX_AGE,
+            config.cors.max_age.to_string(),
+        );
+    if let Some(headers) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+        result.header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers.clone());
+    }
+    result.finish()
+}
@@ -0,0 +1,364 @@
+use actix_web::error::BlockingError;
+use actix_web::web::{self, Bytes};
+use futures::{future, try_ready, Async, Future, Poll, Stream};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes read from a body file at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Makes file names unique within a process, the start time across restarts.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// File I/O running on the thread pool.
+type Io<T> = Box<dyn Future<Item = T, Error = io::Error>>;
+
+/**
+ * A cached response in `cache.dir`, as `<name>.body` with the body and
+ * `<name>.json` with its `Meta`. Bodies are written to `<name>.tmp` first.
+ */
+#[derive(Clone, PartialEq)]
+pub struct Files {
+    base: PathBuf,
+    pub len: u64,
+}
+
+/// Everything but the body, as stored next to it.
+#[derive(Serialize, Deserialize)]
+pub struct Meta {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub vary: Vec<(String, Option<String>)>,
+    /// Unix time.
+    pub stored: u64,
+    pub initial_age: u64,
+    pub lifetime: u64,
+}
+
+/**
+ * Writes a body to disk as it is passed on to the client, one chunk at a
+ * time on the thread pool.
+ */
+pub struct Writer {
+    /// `None` while the file is being created or a chunk written.
+    file: Option<BufWriter<File>>,
+    writing: Option<Io<BufWriter<File>>>,
+    base: PathBuf,
+    len: u64,
+    done: bool,
+}
+
+/// Streams a body file back, reading on the thread pool.
+pub struct FileStream {
+    /// `None` while a chunk is being read, or at the end.
+    file: Option<File>,
+    reading: Option<Io<(File, Vec<u8>)>>,
+}
+
+impl Files {
+    fn path(&self, extension: &str) -> PathBuf {
+        self.base.with_extension(extension)
+    }
+
+    pub fn open(&self) -> impl Future<Item = FileStream, Error = io::Error> {
+        let path = self.path("body");
+        blocking(move || File::open(path)).map(|file| FileStream {
+            file: Some(file),
+            reading: None,
+        })
+    }
+
+    /// Writes `meta` in the background. Bodies without it are not loaded again.
+    pub fn save(&self, meta: &Meta) -> io::Result<()> {
+        let json = serde_json::to_vec(meta).map_err(io::Error::other)?;
+        let (temporary, path) = (self.path("json.tmp"), self.path("json"));
+        in_background(move || {
+            fs::write(&temporary, json)?;
+            fs::rename(temporary, path)
+        });
+        Ok(())
+    }
+
+    /// Removes the files in the background, ignoring ones that are gone already.
+    pub fn remove(&self) {
+        let (json, body) = (self.path("json"), self.path("body"));
+        in_background(move || {
+            let _ = fs::remove_file(json);
+            let _ = fs::remove_file(body);
+            Ok(())
+        });
+    }
+}
+
+impl Writer {
+    /// Starts creating the body file, which `poll_ready` waits for.
+    pub fn create(dir: &Path) -> Writer {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!(
+            "{:x}-{:x}-{:x}",
+            started.as_secs(),
+            started.subsec_nanos(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let base = dir.join(name);
+        let temporary = base.with_extension("tmp");
+        Writer {
+            file: None,
+            writing: Some(Box::new(blocking(move || {
+                File::create(temporary).map(BufWriter::new)
+            }))),
+            base,
+            len: 0,
+            done: false,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Ready once the previous chunk is written and the next may be.
+    pub fn poll_ready(&mut self) -> Poll<(), io::Error> {
+        if let Some(writing) = &mut self.writing {
+            let file = try_ready!(writing.poll());
+            self.writing = None;
+            self.file = Some(file);
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Starts writing a chunk, once `poll_ready` is ready.
+    pub fn write(&mut self, chunk: Bytes) {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => return,
+        };
+        self.len += chunk.len() as u64;
+        self.writing = Some(Box::new(blocking(move || {
+            file.write_all(&chunk).map(|()| file)
+        })));
+    }
+
+    /// Moves the complete body in place once the last chunk is written.
+    pub fn finish(mut self) -> impl Future<Item = Files, Error = io::Error> {
+        let written: Io<BufWriter<File>> = match (self.writing.take(), self.file.take()) {
+            (Some(writing), _) => writing,
+            (None, Some(file)) => Box::new(future::ok(file)),
+            (None, None) => Box::new(future::err(io::Error::other("body was not written"))),
+        };
+        let temporary = self.base.with_extension("tmp");
+        let body = self.base.with_extension("body");
+        written
+            .and_then(|mut file| {
+                blocking(move || {
+                    file.flush()?;
+                    fs::rename(temporary, body)
+                })
+            })
+            // Dropping the writer before the body is in place removes it.
+            .map(move |()| {
+                self.done = true;
+                Files {
+                    base: self.base.clone(),
+                    len: self.len,
+                }
+            })
+    }
+}
+
+impl Drop for Writer {
+    /**
+     * Bodies that are not finished, failed or too large, are not kept. A file
+     * still being created may outlive this, until `load` removes it.
+     */
+    fn drop(&mut self) {
+        if !self.done {
+            let file = self.file.take();
+            let temporary = self.base.with_extension("tmp");
+            in_background(move || {
+                drop(file);
+                fs::remove_file(temporary)
+            });
+        }
+    }
+}
+
+impl Stream for FileStream {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, io::Error> {
+        if self.reading.is_none() {
+            let mut file = match self.file.take() {
+                Some(file) => file,
+                None => return Ok(Async::Ready(None)),
+            };
+            self.reading = Some(Box::new(blocking(move || {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let read = file.read(&mut chunk)?;
+                chunk.truncate(read);
+                Ok((file, chunk))
+            })));
+        }
+        let (file, chunk) = match &mut self.reading {
+            Some(reading) => try_ready!(reading.poll()),
+            None => return Ok(Async::Ready(None)),
+        };
+        self.reading = None;
+        if chunk.is_empty() {
+            return Ok(Async::Ready(None));
+        }
+        self.file = Some(file);
+        Ok(Async::Ready(Some(Bytes::from(chunk))))
+    }
+}
+
+/// Runs file I/O on the thread pool instead of the worker.
+fn blocking<F, T>(io: F) -> impl Future<Item = T, Error = io::Error>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(io).map_err(|err| match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => io::Error::other("file I/O was canceled"),
+    })
+}
+
+/**
+ * Runs file I/O nobody waits for on a thread of its own, in the order it
+ * is queued, so a body removed after its meta is saved stays removed.
+ */
+fn in_background<F>(io: F)
+where
+    F: FnOnce() -> io::Result<()> + Send + 'static,
+{
+    static QUEUE: OnceLock<mpsc::Sender<Box<dyn FnOnce() + Send>>> = OnceLock::new();
+    let queue = QUEUE.get_or_init(|| {
+        let (queue, jobs) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        thread::spawn(move || jobs.into_iter().for_each(|job| job()));
+        queue
+    });
+    let _ = queue.send(Box::new(move || {
+        let _ = io();
+    }));
+}
+
+/**
+ * Reads the responses stored in `dir`, oldest first, removing files left
+ * over from responses that were not completely stored. Only files named
+ * like `Writer::create` names them are touched.
+ */
+pub fn load(dir: &Path) -> io::Result<Vec<(Files, Meta)>> {
+    fs::create_dir_all(dir)?;
+    let mut stored = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let (name, extension) = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('.'))
+        {
+            Some((name, extension)) if is_cache_name(name) => (name, extension),
+            // Not written by the cache.
+            _ => continue,
+        };
+        let base = dir.join(name);
+        match extension {
+            "json" => {}
+            "body" if base.with_extension("json").exists() => continue,
+            "body" | "tmp" | "json.tmp" => {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            _ => continue,
+        }
+        let len = match fs::metadata(base.with_extension("body")) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+        };
+        let files = Files { base, len };
+        match fs::read(&path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Meta>(&json).ok())
+        {
+            Some(meta) => stored.push((files, meta)),
+            None => files.remove(),
+        }
+    }
+    stored.sort_by_key(|(_, meta)| meta.stored);
+    Ok(stored)
+}
+
+/// Whether a file name without extension is one `Writer::create` makes.
+fn is_cache_name(name: &str) -> bool {
+    let parts = name.split('-').collect::<Vec<_>>();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_other_files_alone() {
+        let dir = std::env::temp_dir().join(format!("actix-cors-load-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let foreign = [
+            "package.json",
+            "notes.tmp",
+            "image.body",
+            "not-a-cache.json",
+            "1-2.body",
+        ];
+        for name in &foreign {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+        for name in &[
+            "1a-2b-3c.tmp",
+            "1a-2b-3d.body",
+            "1a-2b-3e.json",
+            "1a-2b-3f.json.tmp",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        assert!(load(&dir).unwrap().is_empty());
+        let mut left = fs::read_dir(&dir)
+            .unwrap()
+            .map(|file| file.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        let mut foreign = foreign
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        foreign.sort();
+        assert_eq!(left, foreign);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_names() {
+        assert!(is_cache_name("5f3a1c2b-1dcd6500-0"));
+        assert!(!is_cache_name("package"));
+        assert!(!is_cache_name("not-a-cache"));
+        assert!(!is_cache_name("1a--2b"));
+        assert!(!is_cache_name("1a-2b-3c-4d"));
+    }
+}
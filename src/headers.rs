@@ -0,0 +1,141 @@
+use crate::config::{Config, CookiePolicy};
+use crate::hop_by_hop::HopByHop;
+use crate::redirects::LocationRewrite;
+use crate::timeouts;
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::{header, HeaderMap, HeaderName};
+use actix_web::HttpRequest;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+/// Client request headers forwarded upstream.
+#[derive(Deserialize, Default)]
+#[serde(try_from = "Vec<String>")]
+pub enum HeaderPolicy {
+    /// Everything except hop-by-hop headers and those in `NOT_FORWARDED`.
+    #[default]
+    All,
+    Only(Vec<HeaderName>),
+}
+
+/**
+ * The client sets `host` from the upstream URL and `request_body` decides
+ * how the body is framed.
+ */
+const NOT_FORWARDED: &[HeaderName] = &[header::HOST, header::CONTENT_LENGTH];
+
+/**
+ * Set by the proxy itself. `content-length` is left out as the body is
+ * streamed.
+ */
+const NOT_RETURNED: &[HeaderName] = &[
+    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+    header::CONTENT_LENGTH,
+];
+
+impl TryFrom<Vec<String>> for HeaderPolicy {
+    type Error = String;
+
+    /// `*` allows all headers.
+    fn try_from(names: Vec<String>) -> Result<HeaderPolicy, String> {
+        if names.iter().any(|name| name == "*") {
+            return Ok(HeaderPolicy::All);
+        }
+        HeaderList::try_from(names).map(|list| HeaderPolicy::Only(list.0))
+    }
+}
+
+impl HeaderPolicy {
+    pub fn allows(&self, name: &HeaderName) -> bool {
+        match self {
+            HeaderPolicy::All => true,
+            HeaderPolicy::Only(names) => names.contains(name),
+        }
+    }
+}
+
+/// Header names as written in the configuration.
+#[derive(Deserialize, Default)]
+#[serde(try_from = "Vec<String>")]
+pub struct HeaderList(pub Vec<HeaderName>);
+
+impl TryFrom<Vec<String>> for HeaderList {
+    type Error = String;
+
+    fn try_from(names: Vec<String>) -> Result<HeaderList, String> {
+        names
+            .iter()
+            .map(|name| {
+                HeaderName::from_bytes(name.to_lowercase().as_bytes())
+                    .map_err(|_| format!("Invalid header name {}", name))
+            })
+            .collect::<Result<_, _>>()
+            .map(HeaderList)
+    }
+}
+
+/**
+ * Copies the client's headers to the upstream request, replacing any the
+ * client set by default. `cookie` follows the cookie policy regardless of
+ * the header policy.
+ */
+pub fn forward_request_headers(req: &HttpRequest, upstream: &mut HeaderMap, config: &Config) {
+    let hop_by_hop = HopByHop::of(req.headers());
+    for name in req.headers().keys() {
+        if hop_by_hop.contains(name) || !is_forwarded(name, config) {
+            continue;
+        }
+        upstream.remove(name);
+        for value in req.headers().get_all(name) {
+            upstream.append(name.clone(), value.clone());
+        }
+    }
+}
+
+fn is_forwarded(name: &HeaderName, config: &Config) -> bool {
+    if NOT_FORWARDED.contains(name)
+        || name == timeouts::OVERRIDE_HEADER
+        // With authentication off the header belongs to the target.
+        || (*name == config.auth.header.0 && !config.auth.keys.is_empty())
+    {
+        return false;
+    }
+    if name == header::COOKIE {
+        return config.headers.cookies == CookiePolicy::Forward;
+    }
+    config.headers.forward.allows(name)
+}
+
+/**
+ * Copies upstream response headers, except hop-by-hop and CORS headers.
+ * Returns the names of the copied headers.
+ */
+pub fn return_response_headers(
+    upstream: &HeaderMap,
+    result: &mut HttpResponseBuilder,
+    rewrite: Option<&LocationRewrite>,
+    config: &Config,
+) -> Vec<HeaderName> {
+    let hop_by_hop = HopByHop::of(upstream);
+    let headers = upstream.iter().filter(|(name, _)| {
+        !hop_by_hop.contains(name)
+            && !NOT_RETURNED.contains(name)
+            && (*name != header::SET_COOKIE || config.headers.cookies == CookiePolicy::Forward)
+    });
+
+    let mut returned = Vec::new();
+    for (name, value) in headers {
+        match rewrite {
+            Some(rewrite) if name == header::LOCATION || name == header::CONTENT_LOCATION => {
+                result.header(name.clone(), rewrite.apply(value))
+            }
+            _ => result.header(name.clone(), value.clone()),
+        };
+        if !returned.contains(name) {
+            returned.push(name.clone());
+        }
+    }
+    returned
+}
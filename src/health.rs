@@ -0,0 +1,61 @@
+use crate::config::Config;
+use actix_web::client::Client;
+use actix_web::http::uri::Uri;
+use actix_web::{web, Error, HttpResponse};
+use futures::{future, Future};
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// `/readyz` fails unless this URL answers without a 5xx status.
+    pub upstream: Option<UpstreamCheck>,
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct UpstreamCheck(Uri);
+
+impl TryFrom<String> for UpstreamCheck {
+    type Error = String;
+
+    fn try_from(url: String) -> Result<UpstreamCheck, String> {
+        match url.parse::<Uri>() {
+            Ok(uri) if uri.host().is_some() && crate::is_valid_scheme(uri.scheme_str()) => {
+                Ok(UpstreamCheck(uri))
+            }
+            _ => Err(format!("Invalid upstream URL {}", url)),
+        }
+    }
+}
+
+/// Liveness, the process is up and answering.
+pub fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok\n")
+}
+
+/**
+ * Readiness, checking `health.upstream` when configured. The check is
+ * operator configured, so it skips the target rules.
+ */
+pub fn readyz(
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> impl Future<Item = HttpResponse, Error = Error> {
+    let UpstreamCheck(uri) = match &config.health.upstream {
+        Some(check) => check,
+        None => return future::Either::A(future::ok(healthz())),
+    };
+
+    future::Either::B(client.get(uri.clone()).send().then(|result| {
+        Ok(match result {
+            Ok(response) if !response.status().is_server_error() => healthz(),
+            Ok(response) => HttpResponse::ServiceUnavailable()
+                .body(format!("upstream answered {}\n", response.status())),
+            Err(err) => {
+                HttpResponse::ServiceUnavailable().body(format!("upstream unavailable: {}\n", err))
+            }
+        })
+    }))
+}
@@ -0,0 +1,41 @@
+use actix_web::http::{header, HeaderMap, HeaderName};
+
+/**
+ * Headers that only concern a single connection, RFC 7230 section 6.1, plus
+ * the non-standard `proxy-connection` still sent by some clients.
+ */
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The hop-by-hop headers of one message.
+pub struct HopByHop {
+    listed: Vec<String>,
+}
+
+impl HopByHop {
+    /// Headers named in `connection` are hop-by-hop as well.
+    pub fn of(headers: &HeaderMap) -> HopByHop {
+        let listed = headers
+            .get_all(header::CONNECTION)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        HopByHop { listed }
+    }
+
+    pub fn contains(&self, name: &HeaderName) -> bool {
+        HOP_BY_HOP.contains(&name.as_str())
+            || self.listed.iter().any(|listed| listed == name.as_str())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::config::Config;
+use crate::rate_limit::Bucket;
+use crate::ProxyError;
+use actix_web::web;
+use futures::future::{self, Loop};
+use futures::{Future, Poll, Stream};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_timer::Delay;
+
+/// How often a queued request checks for a free connection slot.
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Hosts kept before idle ones are dropped.
+const MAX_IDLE_HOSTS: usize = 10_000;
+
+/**
+ * Limits per upstream host, shared by all clients, so busy third-party APIs
+ * are not flooded through the proxy.
+ */
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostLimitsConfig {
+    /// Requests in flight per host, 0 for no limit.
+    pub max_concurrent: usize,
+    /// Requests per host and period, 0 for no limit.
+    pub requests: u32,
+    pub period: u64,
+    /// Seconds a request waits for its host's budget before 503, 0 to not wait.
+    pub queue_timeout: u64,
+}
+
+impl Default for HostLimitsConfig {
+    fn default() -> HostLimitsConfig {
+        HostLimitsConfig {
+            max_concurrent: 0,
+            requests: 0,
+            period: 60,
+            queue_timeout: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HostLimiter {
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+#[derive(Default)]
+struct HostState {
+    active: usize,
+    bucket: Option<Bucket>,
+}
+
+/// Held while a request to a host is in flight, until its response body is read.
+pub struct Permit {
+    limiter: web::Data<HostLimiter>,
+    /// `None` when hosts are not limited.
+    host: Option<String>,
+}
+
+/**
+ * Waits for a slot and a request token for `host`, queueing for up to
+ * `upstream_limits.queue_timeout` seconds.
+ */
+pub fn acquire(
+    limiter: web::Data<HostLimiter>,
+    host: &str,
+    config: &Config,
+) -> impl Future<Item = Permit, Error = ProxyError> {
+    let rules = config.upstream_limits;
+    if rules.max_concurrent == 0 && rules.requests == 0 {
+        return future::Either::A(future::ok(Permit {
+            limiter,
+            host: None,
+        }));
+    }
+
+    let host = host.to_string();
+    let deadline = Instant::now() + Duration::from_secs(rules.queue_timeout);
+    future::Either::B(future::loop_fn((), move |()| {
+        let wait = match limiter.try_acquire(&host, &rules) {
+            Ok(()) => {
+                return future::Either::A(future::ok(Loop::Break(Permit {
+                    limiter: limiter.clone(),
+                    host: Some(host.clone()),
+                })))
+            }
+            Err(wait) => wait,
+        };
+        let retry_at = Instant::now() + wait;
+        if retry_at > deadline {
+            return future::Either::A(future::failed(ProxyError::UpstreamBusy(host.clone())));
+        }
+        future::Either::B(
+            Delay::new(retry_at)
+                .map(Loop::Continue)
+                .map_err(|_| ProxyError::InternalServerError),
+        )
+    }))
+}
+
+impl HostLimiter {
+    /// Fails with the time until it is worth trying again.
+    fn try_acquire(&self, host: &str, rules: &HostLimitsConfig) -> Result<(), Duration> {
+        let capacity = f64::from(rules.requests);
+        let rate = capacity / rules.period as f64;
+        let now = Instant::now();
+
+        let mut hosts = self.hosts.lock().unwrap_or_else(|err| err.into_inner());
+        if hosts.len() > MAX_IDLE_HOSTS {
+            hosts.retain(|_, state| {
+                state.active > 0
+                    || state
+                        .bucket
+                        .as_ref()
+                        .is_some_and(|bucket| !bucket.is_full(now, rate, capacity))
+            });
+        }
+        let state = hosts.entry(host.to_string()).or_default();
+        if rules.max_concurrent > 0 && state.active >= rules.max_concurrent {
+            return Err(POLL_INTERVAL);
+        }
+        if rules.requests > 0 {
+            state
+                .bucket
+                .get_or_insert_with(|| Bucket::full(capacity, now))
+                .take(now, rate, capacity)
+                .map_err(Duration::from_secs_f64)?;
+        }
+        state.active += 1;
+        Ok(())
+    }
+}
+
+impl Permit {
+    /// Keeps the permit until `stream`, a response body, is dropped.
+    pub fn hold<S>(self, stream: S) -> Limited<S> {
+        Limited {
+            stream,
+            _permit: self,
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(host) = &self.host {
+            let mut hosts = self
+                .limiter
+                .hosts
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            if let Some(state) = hosts.get_mut(host) {
+                state.active -= 1;
+                if state.active == 0 && state.bucket.is_none() {
+                    hosts.remove(host);
+                }
+            }
+        }
+    }
+}
+
+pub struct Limited<S> {
+    stream: S,
+    _permit: Permit,
+}
+
+impl<S: Stream> Stream for Limited<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        self.stream.poll()
+    }
+}
@@ -1,61 +1,341 @@
-use actix_web::client::{Client, SendRequestError};
-use actix_web::http::{uri::Uri, Method};
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, ResponseError};
-use futures::{future, Future};
-use std::fmt;
+use access_log::{ErrorCode, UpstreamLatency};
+use actix_web::body::{Body, BodyStream, SizedStream};
+use actix_web::client::{Client, ClientResponse, ConnectError, Connector, SendRequestError};
+use actix_web::dev::{HttpResponseBuilder, Payload, PayloadStream, Service};
+use actix_web::error::PayloadError;
+use actix_web::http::{header, uri::Uri, HeaderMap, Method, StatusCode};
+use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer, ResponseError};
+use cache::{Cache, Entry, Lookup};
+use config::{Config, ConfigError};
+use futures::{future, Future, Stream};
+use host_limits::{HostLimiter, Permit};
+use metrics::Metrics;
+use rate_limit::RateLimiter;
+use redirects::{Hop, LocationRewrite};
+use serde_json::json;
+use ssrf::Target;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use std::{fmt, io, mem, process};
 
-const USAGE: &str = "Usage: GET /URL\n";
+mod access_log;
+mod auth;
+mod cache;
+mod config;
+mod cors;
+mod disk_cache;
+mod headers;
+mod health;
+mod hop_by_hop;
+mod host_limits;
+mod metrics;
+mod rate_limit;
+mod redirects;
+mod signing;
+mod ssrf;
+mod targets;
+mod timeouts;
 
-fn main() -> std::io::Result<()> {
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let server = HttpServer::new(|| {
+const USAGE: &str = "Usage: METHOD /URL\n";
+
+fn main() -> io::Result<()> {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", ConfigError::Help);
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    let address = (config.bind, config.port);
+    let config = web::Data::new(config);
+    let metrics = web::Data::new(Metrics::default());
+    let limiter = web::Data::new(RateLimiter::default());
+    let host_limiter = web::Data::new(HostLimiter::default());
+    let cache = match Cache::open(&config.cache) {
+        Ok(cache) => web::Data::new(cache),
+        Err(err) => {
+            eprintln!("Unable to open cache directory: {}", err);
+            process::exit(2);
+        }
+    };
+    let server = HttpServer::new(move || {
         App::new()
-            .data(Client::new())
+            .register_data(config.clone())
+            .register_data(metrics.clone())
+            .register_data(limiter.clone())
+            .register_data(host_limiter.clone())
+            .register_data(cache.clone())
+            .wrap_fn({
+                let config = config.clone();
+                move |req, srv| {
+                    let config = config.clone();
+                    srv.call(req).map(move |mut res| {
+                        cors::add_response_origin_headers(&mut res, &config);
+                        res
+                    })
+                }
+            })
+            .wrap_fn({
+                let config = config.clone();
+                let metrics = metrics.clone();
+                move |req, srv| access_log::log_request(req, srv, &config, &metrics)
+            })
+            .data(build_client(&config))
             .service(web::resource("/").to(|| USAGE))
+            .service(web::resource("/metrics").to(metrics::metrics))
+            .service(web::resource("/healthz").to(health::healthz))
+            .service(web::resource("/readyz").to_async(health::readyz))
             .default_service(web::route().to_async(proxy))
     })
-    .bind(["0.0.0.0:", &port].concat())?;
+    .bind(address)?;
 
-    println!("Listening on port {}", &port);
+    println!("Listening on {}:{}", address.0, address.1);
 
     server.run()
 }
 
+fn build_client(config: &Config) -> Client {
+    let connector = Connector::new()
+        .timeout(Duration::from_secs(config.timeouts.connect))
+        .finish();
+    Client::build()
+        .connector(connector)
+        .timeout(Duration::from_secs(config.timeouts.request))
+        .finish()
+}
+
+/// Errors are answered here rather than by actix, so they can name the target.
 fn proxy(
     req: HttpRequest,
+    payload: web::Payload,
     client: web::Data<Client>,
+    config: web::Data<Config>,
+    limiter: web::Data<RateLimiter>,
+    host_limiter: web::Data<HostLimiter>,
+    cache: web::Data<Cache>,
 ) -> impl Future<Item = HttpResponse, Error = ProxyError> {
-    is_get_method(req)
-        .and_then(parse_uri)
-        .and_then(|uri| proxy_request(uri, client))
+    try_proxy(
+        req.clone(),
+        payload,
+        client,
+        config.clone(),
+        limiter,
+        host_limiter,
+        cache,
+    )
+    .or_else({
+        let req = req.clone();
+        move |err| Ok(err.to_response(&req, &config))
+    })
+    .map(move |mut response| {
+        rate_limit::add_headers(&req, response.headers_mut());
+        response
+    })
+}
+
+fn try_proxy(
+    req: HttpRequest,
+    payload: web::Payload,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+    limiter: web::Data<RateLimiter>,
+    host_limiter: web::Data<HostLimiter>,
+    cache: web::Data<Cache>,
+) -> impl Future<Item = HttpResponse, Error = ProxyError> {
+    if let Err(err) = cors::allow_origin(&req, &config) {
+        return future::Either::A(future::failed(err));
+    }
+    if cors::is_preflight(&req) {
+        let response = cors::preflight_response(&req, &config);
+        return future::Either::A(future::ok(response));
+    }
+    let key = match signing::verify(&req, &config) {
+        Ok(true) => None,
+        Ok(false) => match auth::authenticate(&req, &config) {
+            Ok(key) => key,
+            Err(err) => return future::Either::A(future::failed(err)),
+        },
+        Err(err) => return future::Either::A(future::failed(err)),
+    };
+    if let Err(err) = limiter.check(&req, key, &config) {
+        return future::Either::A(future::failed(err));
+    }
+
+    let timeout = match timeouts::total(&req, &config) {
+        Ok(timeout) => timeout,
+        Err(err) => return future::Either::A(future::failed(err)),
+    };
+    let body = match request_body(&req, payload, config.limits.max_request_body) {
+        Ok(body) => body,
+        Err(err) => return future::Either::A(future::failed(err)),
+    };
+    future::Either::B(timeouts::with_timeout(
+        is_supported_method(req.clone())
+            .and_then({
+                let config = config.clone();
+                move |req| parse_uri(req, &config)
+            })
+            .and_then({
+                let config = config.clone();
+                move |uri| check_target(uri, key, &config)
+            })
+            .and_then(move |target| {
+                let lookup = cache.lookup(&req, &target.uri, &config);
+                if let Lookup::Fresh(entry) = &lookup {
+                    return future::Either::A(cached_response(&req, entry, &target.uri, &config));
+                }
+                let mut hop = Hop::new(req.method().clone(), target, body, key);
+                if let Lookup::Stale(entry) = &lookup {
+                    hop.conditional = entry.conditional_headers();
+                }
+                future::Either::B(proxy_request(
+                    req,
+                    hop,
+                    lookup,
+                    client,
+                    host_limiter,
+                    cache,
+                    config,
+                ))
+            }),
+        timeout,
+    ))
 }
 
 /**
- * - catch all `default_service` does not support `web::get` method guard
+ * - catch all `default_service` does not support method guards
  * - fn cannot branch into two different futures, https://gist.github.com/arve0/09d899a7ad718ca5623f56c5c03856ca
+ *
  * -> chain this fn instead
  */
-fn is_get_method(req: HttpRequest) -> impl Future<Item = HttpRequest, Error = ProxyError> {
-    if req.method() == Method::GET {
-        future::ok(req)
-    } else {
-        future::failed(ProxyError::MethodNotSupported)
+fn is_supported_method(req: HttpRequest) -> impl Future<Item = HttpRequest, Error = ProxyError> {
+    match *req.method() {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::PATCH
+        | Method::DELETE
+        | Method::OPTIONS => future::ok(req),
+        _ => future::failed(ProxyError::MethodNotSupported),
+    }
+}
+
+/**
+ * Streams the incoming body to upstream, keeping `content-length` when the
+ * client sent one. Requests without a body are sent without one, as chunked
+ * GET requests are rejected by some servers.
+ *
+ * Bodies over `limit` bytes are refused up front when the length is known,
+ * otherwise the stream is cut off once the limit is passed.
+ */
+fn request_body(req: &HttpRequest, payload: web::Payload, limit: u64) -> Result<Body, ProxyError> {
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let limit = if limit == 0 { u64::MAX } else { limit };
+
+    let mut received = 0;
+    let payload = payload.and_then(move |chunk| {
+        received += chunk.len() as u64;
+        if received > limit {
+            Err(PayloadError::Overflow)
+        } else {
+            Ok(chunk)
+        }
+    });
+
+    match content_length {
+        Some(length) if length > limit => Err(ProxyError::PayloadTooLarge),
+        Some(0) => Ok(Body::Empty),
+        Some(length) => Ok(Body::from_message(SizedStream::new(
+            length,
+            payload.map_err(Error::from),
+        ))),
+        None if req.headers().contains_key(header::TRANSFER_ENCODING) => {
+            Ok(Body::from_message(BodyStream::new(payload)))
+        }
+        None => Ok(Body::Empty),
     }
 }
 
-fn parse_uri(req: HttpRequest) -> impl Future<Item = Uri, Error = ProxyError> {
+fn parse_uri(req: HttpRequest, config: &Config) -> impl Future<Item = Uri, Error = ProxyError> {
     if req.path().is_empty() {
         return future::failed(ProxyError::UnableToParseUri);
-    } else if let Ok(parsed) = get_whole_path(&req).parse::<Uri>() {
-        if parsed.host() != None && is_valid_scheme(parsed.scheme_str()) {
-            return future::ok(parsed);
+    }
+    future::result(parse_absolute_uri(&get_whole_path(&req, config)))
+}
+
+fn parse_absolute_uri(uri: &str) -> Result<Uri, ProxyError> {
+    match uri.parse::<Uri>() {
+        Ok(parsed) if parsed.host().is_some() && is_valid_scheme(parsed.scheme_str()) => Ok(parsed),
+        _ => Err(ProxyError::UnableToParseUri),
+    }
+}
+
+/**
+ * Runs the host allowlist, API key and address checks on a target, also
+ * used for redirects.
+ */
+fn check_target(
+    uri: Uri,
+    key: Option<usize>,
+    config: &Config,
+) -> impl Future<Item = Target, Error = ProxyError> {
+    let allow_private = config.targets.allow_private;
+    let rules = config.targets.address_rules(uri.host().unwrap_or_default());
+    is_allowed_target(uri, key, config)
+        .and_then(move |uri| ssrf::resolve(uri, allow_private, rules))
+}
+
+fn is_allowed_target(
+    uri: Uri,
+    key: Option<usize>,
+    config: &Config,
+) -> impl Future<Item = Uri, Error = ProxyError> {
+    match uri.host() {
+        Some(host) if !config.targets.is_allowed(host) => {
+            future::failed(ProxyError::TargetNotAllowed(host.to_string()))
+        }
+        Some(host) if !auth::allows_target(key, host, config) => {
+            future::failed(ProxyError::KeyNotAllowed)
         }
+        Some(_) => future::ok(uri),
+        None => future::failed(ProxyError::TargetNotAllowed(String::new())),
     }
-    future::failed(ProxyError::UnableToParseUri)
 }
 
-fn get_whole_path(req: &HttpRequest) -> String {
-    [&req.path()[1..], "?", req.query_string()].concat()
+fn get_whole_path(req: &HttpRequest, config: &Config) -> String {
+    [&req.path()[1..], "?", &upstream_query(req, config)].concat()
+}
+
+/// The query string without the proxy's own parameters.
+fn upstream_query(req: &HttpRequest, config: &Config) -> String {
+    req.query_string()
+        .split('&')
+        .filter(|pair| {
+            !auth::is_key_param(pair, config) && !signing::is_signing_param(pair, config)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The target as the client asked for it, for error responses.
+fn requested_target(req: &HttpRequest, config: &Config) -> Option<String> {
+    match (
+        req.path().get(1..).unwrap_or(""),
+        upstream_query(req, config),
+    ) {
+        ("", _) => None,
+        (path, ref query) if query.is_empty() => Some(path.to_string()),
+        (path, query) => Some([path, "?", &query].concat()),
+    }
 }
 
 fn is_valid_scheme(scheme: Option<&str>) -> bool {
@@ -66,62 +346,339 @@ fn is_valid_scheme(scheme: Option<&str>) -> bool {
     }
 }
 
+/**
+ * Sends the request upstream, following redirects, and streams the response
+ * back while recording it in the cache.
+ */
 fn proxy_request(
-    uri: Uri,
+    req: HttpRequest,
+    hop: Hop,
+    lookup: Lookup,
     client: web::Data<Client>,
+    host_limiter: web::Data<HostLimiter>,
+    cache: web::Data<Cache>,
+    config: web::Data<Config>,
 ) -> impl Future<Item = HttpResponse, Error = ProxyError> {
-    client
-        .get(uri)
-        .no_decompress()
-        .send()
-        .map_err(|err| match err {
-            SendRequestError::Url(error) => ProxyError::RequestError(error.to_string()),
-            SendRequestError::Connect(error) => ProxyError::RequestError(error.to_string()),
-            _ => ProxyError::InternalServerError,
-        })
-        .and_then(|response| {
-            let mut result = HttpResponse::build(response.status());
-            let headers = response.headers().iter().filter(|(h, _)| {
-                *h != "connection"
-                    && *h != "access-control-allow-origin"
-                    && *h != "content-length"
-            });
-            for (header_name, header_value) in headers {
-                result.header(header_name.clone(), header_value.clone());
+    let first = hop.target.uri.clone();
+    let started = Instant::now();
+
+    future::loop_fn(hop, {
+        let req = req.clone();
+        let first = first.clone();
+        let config = config.clone();
+        move |mut hop| {
+            let body = mem::replace(&mut hop.body, Body::Empty);
+            let with_credentials = hop.is_same_origin(&first);
+            let permit = host_limits::acquire(
+                host_limiter.clone(),
+                hop.target.uri.host().unwrap_or_default(),
+                &config,
+            );
+            send_upstream(&req, &hop, body, with_credentials, &client, permit, &config).and_then({
+                let config = config.clone();
+                move |response| redirects::follow(response, &hop, &config)
+            })
+        }
+    })
+    .and_then(move |(response, uri)| {
+        req.extensions_mut()
+            .insert(UpstreamLatency(started.elapsed()));
+        // Redirects are followed again on every request, only the URL asked for is cached.
+        let lookup = if uri == first { lookup } else { Lookup::Bypass };
+        if let Lookup::Stale(stale) = &lookup {
+            if response.status() == StatusCode::NOT_MODIFIED {
+                let entry = cache.refresh(stale, &req, &uri, response.headers(), &config);
+                return future::Either::A(cached_response(&req, &entry, &uri, &config));
             }
-            result.header("access-control-allow-origin", "*");
-            Ok(result.streaming(response))
+        }
+
+        let mut result =
+            response_builder(&req, response.status(), response.headers(), &uri, &config);
+        if config.cache.max_size > 0 {
+            result.header(cache::CACHE_HEADER, "MISS");
+        }
+        let body = Cache::record(cache, &lookup, &req, &uri, response, config);
+        future::Either::B(future::ok(result.streaming(body)))
+    })
+}
+
+/// Status and headers of an upstream response, as returned to the client.
+fn response_builder(
+    req: &HttpRequest,
+    status: StatusCode,
+    upstream: &HeaderMap,
+    uri: &Uri,
+    config: &Config,
+) -> HttpResponseBuilder {
+    let mut result = HttpResponse::build(status);
+    let rewrite = LocationRewrite::new(req, uri, config);
+    let returned =
+        headers::return_response_headers(upstream, &mut result, rewrite.as_ref(), config);
+    // Requests from origins that are not allowed have been refused already.
+    if req.headers().contains_key(header::ORIGIN) {
+        cors::add_expose_headers(&mut result, &returned, config);
+    }
+    result
+}
+
+/**
+ * Answers from the cache, with `304 Not Modified` when the client has the
+ * response. Bodies on disk are streamed.
+ */
+fn cached_response(
+    req: &HttpRequest,
+    entry: &Entry,
+    uri: &Uri,
+    config: &Config,
+) -> impl Future<Item = HttpResponse, Error = ProxyError> {
+    if entry.is_not_modified_for(req) {
+        return future::Either::A(future::ok(
+            response_builder(req, StatusCode::NOT_MODIFIED, &entry.headers, uri, config)
+                .header(header::AGE, entry.age())
+                .header(cache::CACHE_HEADER, "HIT")
+                .finish(),
+        ));
+    }
+    let mut response = response_builder(req, entry.status, &entry.headers, uri, config);
+    response
+        .header(header::AGE, entry.age())
+        .header(cache::CACHE_HEADER, "HIT");
+    let files = match &entry.body {
+        cache::Body::Memory(bytes) => {
+            return future::Either::A(future::ok(response.body(Body::from(bytes.clone()))))
+        }
+        cache::Body::Disk(files) => files,
+    };
+    let len = files.len;
+    future::Either::B(
+        files
+            .open()
+            .map_err(|_| ProxyError::InternalServerError)
+            .map(move |file| {
+                response.body(Body::from_message(SizedStream::new(
+                    len,
+                    file.map_err(Error::from),
+                )))
+            }),
+    )
+}
+
+fn send_upstream(
+    req: &HttpRequest,
+    hop: &Hop,
+    body: Body,
+    with_credentials: bool,
+    client: &Client,
+    permit: impl Future<Item = Permit, Error = ProxyError>,
+    config: &Config,
+) -> impl Future<Item = ClientResponse, Error = ProxyError> {
+    let mut request = client.request(hop.method.clone(), hop.target.uri.clone());
+    headers::forward_request_headers(req, request.headers_mut(), config);
+    for (name, value) in &hop.conditional {
+        request.headers_mut().insert(name.clone(), value.clone());
+    }
+    if !with_credentials {
+        request.headers_mut().remove(header::AUTHORIZATION);
+        request.headers_mut().remove(header::COOKIE);
+    }
+    let request = request
+        .if_some(hop.target.address, |address, request| {
+            request.address(address)
         })
+        .no_decompress();
+    permit.and_then(move |permit| {
+        request
+            .send_body(body)
+            .map(move |response| {
+                response.map_body(move |_, stream| {
+                    Payload::Stream(Box::new(permit.hold(stream)) as PayloadStream)
+                })
+            })
+            .map_err(upstream_error)
+    })
+}
+
+/// Failures reaching upstream are the proxy's or upstream's, not the client's.
+fn upstream_error(err: SendRequestError) -> ProxyError {
+    match err {
+        SendRequestError::Url(error) => ProxyError::RequestError(error.to_string()),
+        SendRequestError::Timeout | SendRequestError::Connect(ConnectError::Timeout) => {
+            ProxyError::Timeout
+        }
+        SendRequestError::Connect(error) => match error {
+            ConnectError::Resolver(_) | ConnectError::NoRecords | ConnectError::Unresolverd => {
+                ProxyError::DnsFailed(error.to_string())
+            }
+            ConnectError::SslError(_) => ProxyError::TlsFailed(error.to_string()),
+            ConnectError::Io(ref io) if io.kind() == io::ErrorKind::ConnectionRefused => {
+                ProxyError::UpstreamUnavailable(error.to_string())
+            }
+            _ => ProxyError::ConnectFailed(error.to_string()),
+        },
+        SendRequestError::Send(error) => ProxyError::ConnectFailed(error.to_string()),
+        SendRequestError::Response(error) => ProxyError::BadUpstreamResponse(error.to_string()),
+        SendRequestError::H2(error) => ProxyError::BadUpstreamResponse(error.to_string()),
+        SendRequestError::Body(ref error)
+            if error.as_response_error().error_response().status()
+                == StatusCode::PAYLOAD_TOO_LARGE =>
+        {
+            ProxyError::PayloadTooLarge
+        }
+        _ => ProxyError::InternalServerError,
+    }
 }
 
 #[derive(Debug)]
 enum ProxyError {
     MethodNotSupported,
+    OriginNotAllowed,
+    Unauthorized,
+    /// The API key is not valid for the origin or target.
+    KeyNotAllowed,
+    InvalidSignature,
+    SignatureExpired,
+    /// Seconds until the client may retry.
+    TooManyRequests(u64),
+    PayloadTooLarge,
     UnableToParseUri,
+    TargetNotAllowed(String),
+    TooManyRedirects,
+    AddressNotAllowed(String, IpAddr),
     RequestError(String),
+    DnsFailed(String),
+    TlsFailed(String),
+    ConnectFailed(String),
+    UpstreamUnavailable(String),
+    /// The host's `upstream_limits` budget is used up.
+    UpstreamBusy(String),
+    BadUpstreamResponse(String),
+    Timeout,
     InternalServerError,
 }
 
+impl ProxyError {
+    fn status(&self) -> StatusCode {
+        use ProxyError::*;
+        match self {
+            MethodNotSupported => StatusCode::METHOD_NOT_ALLOWED,
+            OriginNotAllowed => StatusCode::FORBIDDEN,
+            Unauthorized => StatusCode::UNAUTHORIZED,
+            KeyNotAllowed => StatusCode::FORBIDDEN,
+            InvalidSignature => StatusCode::FORBIDDEN,
+            SignatureExpired => StatusCode::FORBIDDEN,
+            TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            UnableToParseUri => StatusCode::BAD_REQUEST,
+            TargetNotAllowed(_) => StatusCode::FORBIDDEN,
+            AddressNotAllowed(_, _) => StatusCode::FORBIDDEN,
+            TooManyRedirects => StatusCode::BAD_GATEWAY,
+            RequestError(_) => StatusCode::BAD_REQUEST,
+            DnsFailed(_) => StatusCode::BAD_GATEWAY,
+            TlsFailed(_) => StatusCode::BAD_GATEWAY,
+            ConnectFailed(_) => StatusCode::BAD_GATEWAY,
+            UpstreamUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            UpstreamBusy(_) => StatusCode::SERVICE_UNAVAILABLE,
+            BadUpstreamResponse(_) => StatusCode::BAD_GATEWAY,
+            Timeout => StatusCode::GATEWAY_TIMEOUT,
+            InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The JSON error body, naming the target the client asked for.
+    fn to_response(&self, req: &HttpRequest, config: &Config) -> HttpResponse {
+        req.extensions_mut().insert(ErrorCode(self.code()));
+        self.builder()
+            .json(self.body(requested_target(req, config)))
+    }
+
+    fn builder(&self) -> HttpResponseBuilder {
+        let mut result = HttpResponse::build(self.status());
+        if let ProxyError::TooManyRequests(retry_after) = self {
+            result.header(header::RETRY_AFTER, retry_after.to_string());
+        }
+        result
+    }
+
+    fn body(&self, target: Option<String>) -> serde_json::Value {
+        json!({
+            "error": self.to_string(),
+            "code": self.code(),
+            "target": target,
+        })
+    }
+
+    /// Stable identifier for the error, for clients to match on.
+    fn code(&self) -> &'static str {
+        use ProxyError::*;
+        match self {
+            MethodNotSupported => "method_not_supported",
+            OriginNotAllowed => "origin_not_allowed",
+            Unauthorized => "unauthorized",
+            KeyNotAllowed => "key_not_allowed",
+            InvalidSignature => "invalid_signature",
+            SignatureExpired => "signature_expired",
+            TooManyRequests(_) => "rate_limited",
+            PayloadTooLarge => "payload_too_large",
+            UnableToParseUri => "invalid_url",
+            TargetNotAllowed(_) => "target_not_allowed",
+            AddressNotAllowed(_, _) => "address_not_allowed",
+            TooManyRedirects => "too_many_redirects",
+            RequestError(_) => "bad_request",
+            DnsFailed(_) => "dns_failed",
+            TlsFailed(_) => "tls_failed",
+            ConnectFailed(_) => "connect_failed",
+            UpstreamUnavailable(_) => "upstream_unavailable",
+            UpstreamBusy(_) => "upstream_busy",
+            BadUpstreamResponse(_) => "bad_upstream_response",
+            Timeout => "upstream_timeout",
+            InternalServerError => "internal_error",
+        }
+    }
+}
+
 impl fmt::Display for ProxyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use ProxyError::*;
 
         match self {
-            UnableToParseUri => write!(f, "Unable to parse URL\n{}", USAGE),
-            RequestError(reason) => write!(f, "{}\n{}", reason, USAGE),
-            _ => write!(f, "{}", USAGE),
+            MethodNotSupported => write!(f, "Method not supported. {}", USAGE.trim_end()),
+            OriginNotAllowed => write!(f, "Origin is not allowed"),
+            Unauthorized => write!(f, "A valid API key or signed URL is required"),
+            KeyNotAllowed => write!(f, "The API key is not allowed for this origin or target"),
+            InvalidSignature => write!(f, "The URL signature is invalid"),
+            SignatureExpired => write!(f, "The signed URL has expired"),
+            TooManyRequests(retry_after) => {
+                write!(f, "Too many requests, retry in {} seconds", retry_after)
+            }
+            PayloadTooLarge => write!(f, "Request body is too large"),
+            UnableToParseUri => write!(f, "Unable to parse URL. {}", USAGE.trim_end()),
+            TargetNotAllowed(host) => write!(f, "Proxying to {} is not allowed", host),
+            AddressNotAllowed(host, ip) => write!(
+                f,
+                "Proxying to {} is not allowed, it resolves to {}",
+                host, ip
+            ),
+            TooManyRedirects => write!(f, "Too many redirects"),
+            RequestError(reason) => write!(f, "{}", reason),
+            DnsFailed(reason) => write!(f, "Unable to resolve upstream: {}", reason),
+            TlsFailed(reason) => write!(f, "TLS handshake with upstream failed: {}", reason),
+            ConnectFailed(reason) => write!(f, "Unable to connect to upstream: {}", reason),
+            UpstreamUnavailable(reason) => write!(f, "Upstream is unavailable: {}", reason),
+            UpstreamBusy(host) => write!(f, "Too many requests to {}, try again later", host),
+            BadUpstreamResponse(reason) => write!(f, "Invalid response from upstream: {}", reason),
+            Timeout => write!(f, "Upstream did not respond in time"),
+            InternalServerError => write!(f, "Internal server error"),
         }
     }
 }
 
 impl ResponseError for ProxyError {
     fn error_response(&self) -> HttpResponse {
-        use ProxyError::*;
-        match self {
-            MethodNotSupported => HttpResponse::MethodNotAllowed().finish(),
-            UnableToParseUri => HttpResponse::BadRequest().finish(),
-            RequestError(_) => HttpResponse::BadRequest().finish(),
-            InternalServerError => HttpResponse::InternalServerError().finish(),
-        }
+        self.builder().json(self.body(None))
+    }
+
+    /// The default appends the `Display` text as plain text.
+    fn render_response(&self) -> HttpResponse {
+        self.error_response()
     }
 }
@@ -0,0 +1,229 @@
+use actix_web::{web, HttpResponse};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the upstream latency histogram buckets, in seconds.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Distinct upstream hosts labelled, later ones are counted as `other`.
+const MAX_HOSTS: usize = 100;
+
+/**
+ * Counters for `/metrics`, shared by all workers. Requests are recorded by
+ * `access_log` when their response is done.
+ */
+#[derive(Default)]
+pub struct Metrics {
+    in_flight: AtomicI64,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// By status and upstream host, empty for requests that were not sent
+    /// upstream.
+    requests: BTreeMap<(u16, String), u64>,
+    bytes: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+    /// By `ProxyError::code`.
+    errors: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// A finished request, as recorded.
+pub struct Sample<'a> {
+    pub status: u16,
+    pub host: Option<&'a str>,
+    pub upstream: Option<Duration>,
+    pub bytes: u64,
+    pub error: Option<&'static str>,
+}
+
+impl Metrics {
+    pub fn start(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self, sample: Sample) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let mut counters = self.counters.lock().unwrap_or_else(|err| err.into_inner());
+        // Clients pick the hosts, so they could otherwise add series without
+        // end, even for hosts that answer, like wildcard DNS zones.
+        let host = match (sample.upstream, sample.host) {
+            (Some(_), Some(host))
+                if counters.latency.contains_key(host) || counters.latency.len() < MAX_HOSTS =>
+            {
+                host.to_string()
+            }
+            (Some(_), Some(_)) => "other".to_string(),
+            _ => String::new(),
+        };
+        *counters
+            .requests
+            .entry((sample.status, host.clone()))
+            .or_default() += 1;
+        *counters.bytes.entry(host.clone()).or_default() += sample.bytes;
+        if let Some(upstream) = sample.upstream {
+            counters
+                .latency
+                .entry(host)
+                .or_default()
+                .observe(upstream.as_secs_f64());
+        }
+        if let Some(code) = sample.error {
+            *counters.errors.entry(code).or_default() += 1;
+        }
+    }
+
+    /// The Prometheus text exposition format.
+    fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap_or_else(|err| err.into_inner());
+        let mut out = String::new();
+
+        out.push_str("# HELP proxy_requests_total Requests by status and upstream host.\n");
+        out.push_str("# TYPE proxy_requests_total counter\n");
+        for ((status, host), count) in &counters.requests {
+            let _ = writeln!(
+                out,
+                "proxy_requests_total{{status=\"{}\",host=\"{}\"}} {}",
+                status,
+                escape(host),
+                count
+            );
+        }
+
+        out.push_str(
+            "# HELP proxy_response_bytes_total Response body bytes sent by upstream host.\n",
+        );
+        out.push_str("# TYPE proxy_response_bytes_total counter\n");
+        for (host, bytes) in &counters.bytes {
+            let _ = writeln!(
+                out,
+                "proxy_response_bytes_total{{host=\"{}\"}} {}",
+                escape(host),
+                bytes
+            );
+        }
+
+        out.push_str(
+            "# HELP proxy_upstream_latency_seconds Time until upstream response headers.\n",
+        );
+        out.push_str("# TYPE proxy_upstream_latency_seconds histogram\n");
+        for (host, histogram) in &counters.latency {
+            let host = escape(host);
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                let _ = writeln!(
+                    out,
+                    "proxy_upstream_latency_seconds_bucket{{host=\"{}\",le=\"{}\"}} {}",
+                    host, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "proxy_upstream_latency_seconds_bucket{{host=\"{}\",le=\"+Inf\"}} {}",
+                host, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "proxy_upstream_latency_seconds_sum{{host=\"{}\"}} {}",
+                host, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "proxy_upstream_latency_seconds_count{{host=\"{}\"}} {}",
+                host, histogram.count
+            );
+        }
+
+        out.push_str("# HELP proxy_errors_total Errors answered by the proxy by code.\n");
+        out.push_str("# TYPE proxy_errors_total counter\n");
+        for (code, count) in &counters.errors {
+            let _ = writeln!(out, "proxy_errors_total{{code=\"{}\"}} {}", code, count);
+        }
+
+        out.push_str("# HELP proxy_in_flight_requests Requests being answered.\n");
+        out.push_str("# TYPE proxy_in_flight_requests gauge\n");
+        let _ = writeln!(
+            out,
+            "proxy_in_flight_requests {}",
+            self.in_flight.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+impl Histogram {
+    /// Buckets are cumulative, as Prometheus expects.
+    fn observe(&mut self, seconds: f64) {
+        for (bound, count) in BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxied(metrics: &Metrics, host: &str) {
+        metrics.start();
+        metrics.finish(Sample {
+            status: 200,
+            host: Some(host),
+            upstream: Some(Duration::from_millis(10)),
+            bytes: 1,
+            error: None,
+        });
+    }
+
+    #[test]
+    fn caps_host_labels() {
+        let metrics = Metrics::default();
+        for n in 0..MAX_HOSTS + 10 {
+            proxied(&metrics, &format!("{}.example.com", n));
+        }
+        proxied(&metrics, "0.example.com");
+        metrics.start();
+        metrics.finish(Sample {
+            status: 403,
+            host: Some("rejected.example.com"),
+            upstream: None,
+            bytes: 0,
+            error: Some("target_not_allowed"),
+        });
+
+        let counters = metrics.counters.lock().unwrap();
+        // The hosts, `other` and the empty label of the rejected request.
+        assert_eq!(counters.latency.len(), MAX_HOSTS + 1);
+        assert_eq!(counters.bytes.len(), MAX_HOSTS + 2);
+        assert_eq!(counters.bytes["other"], 10);
+        assert_eq!(counters.bytes["0.example.com"], 2);
+        assert_eq!(counters.bytes[""], 0);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::access_log::client_ip;
+use crate::config::Config;
+use crate::ProxyError;
+use actix_web::http::{header, HeaderMap, HeaderName, HeaderValue};
+use actix_web::HttpRequest;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+ * Buckets kept before full, idle ones are dropped. Dropping them scans every
+ * bucket, so it happens at most once per time a bucket takes to refill.
+ */
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/**
+ * A token bucket per client holding `burst` requests, refilled with
+ * `requests` every `period` seconds.
+ */
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// 0 disables rate limiting.
+    pub requests: u32,
+    pub period: u64,
+    /// Bucket size, `requests` when 0.
+    pub burst: u32,
+    pub key: RateLimitKey,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            requests: 0,
+            period: 60,
+            burst: 0,
+            key: RateLimitKey::default(),
+        }
+    }
+}
+
+/// What identifies a client. Requests without an accepted API key or `origin` are limited by IP.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    Ip,
+    ApiKey,
+    Origin,
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_client: HashMap<String, Bucket>,
+    /// When full buckets were last dropped.
+    swept: Option<Instant>,
+}
+
+/// Refills continuously at a rate in tokens per second, up to a capacity.
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The client's quota after a request, for the `ratelimit-*` headers.
+#[derive(Clone, Copy)]
+struct Quota {
+    limit: u32,
+    remaining: u32,
+    /// Seconds until the bucket is full again.
+    reset: u64,
+}
+
+impl RateLimiter {
+    /**
+     * Takes a token from the client's bucket, or fails with the seconds until
+     * one is available. `key` is the index of the API key `auth::authenticate`
+     * accepted, if any.
+     */
+    pub fn check(
+        &self,
+        req: &HttpRequest,
+        key: Option<usize>,
+        config: &Config,
+    ) -> Result<(), ProxyError> {
+        let rules = &config.rate_limit;
+        if rules.requests == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(if rules.burst == 0 {
+            rules.requests
+        } else {
+            rules.burst
+        });
+        let rate = f64::from(rules.requests) / rules.period as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        let refill = Duration::from_secs_f64(capacity / rate);
+        if buckets.by_client.len() > MAX_IDLE_BUCKETS
+            && buckets
+                .swept
+                .is_none_or(|swept| now.duration_since(swept) >= refill)
+        {
+            buckets
+                .by_client
+                .retain(|_, bucket| !bucket.is_full(now, rate, capacity));
+            buckets.swept = Some(now);
+        }
+        let bucket = buckets
+            .by_client
+            .entry(client_key(req, key, config))
+            .or_insert_with(|| Bucket::full(capacity, now));
+        let taken = bucket.take(now, rate, capacity);
+        req.extensions_mut().insert(Quota {
+            limit: capacity as u32,
+            remaining: bucket.tokens as u32,
+            reset: ((capacity - bucket.tokens) / rate).ceil() as u64,
+        });
+
+        taken.map_err(|wait| ProxyError::TooManyRequests((wait.ceil() as u64).max(1)))
+    }
+}
+
+impl Bucket {
+    pub fn full(capacity: f64, now: Instant) -> Bucket {
+        Bucket {
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    /// Takes a token, or fails with the seconds until one is available.
+    pub fn take(&mut self, now: Instant, rate: f64, capacity: f64) -> Result<(), f64> {
+        self.tokens = self.refilled(now, rate).min(capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err((1.0 - self.tokens) / rate)
+        }
+    }
+
+    pub fn is_full(&self, now: Instant, rate: f64, capacity: f64) -> bool {
+        self.refilled(now, rate) >= capacity
+    }
+
+    fn refilled(&self, now: Instant, rate: f64) -> f64 {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * rate
+    }
+}
+
+fn client_key(req: &HttpRequest, key: Option<usize>, config: &Config) -> String {
+    let key = match config.rate_limit.key {
+        RateLimitKey::Ip => None,
+        RateLimitKey::ApiKey => key.map(|index| index.to_string()),
+        RateLimitKey::Origin => req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok())
+            .map(str::to_string),
+    };
+    match key {
+        Some(value) => format!("key:{}", value),
+        None => format!("ip:{}", client_ip(req, config.log.trust_forwarded)),
+    }
+}
+
+/// Sets `ratelimit-limit`, `ratelimit-remaining` and `ratelimit-reset` on limited requests.
+pub fn add_headers(req: &HttpRequest, headers: &mut HeaderMap) {
+    let quota = match req.extensions().get::<Quota>() {
+        Some(quota) => *quota,
+        None => return,
+    };
+    for (name, value) in &[
+        ("ratelimit-limit", quota.limit as u64),
+        ("ratelimit-remaining", quota.remaining as u64),
+        ("ratelimit-reset", quota.reset),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(*value));
+    }
+}
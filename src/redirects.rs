@@ -0,0 +1,212 @@
+use crate::config::Config;
+use crate::ssrf::Target;
+use crate::{check_target, parse_absolute_uri, ProxyError};
+use actix_web::body::Body;
+use actix_web::client::ClientResponse;
+use actix_web::http::{header, uri::Uri, HeaderMap, HeaderValue, Method, StatusCode};
+use actix_web::HttpRequest;
+use futures::future::{self, Loop};
+use futures::Future;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use url::Url;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedirectsConfig {
+    /// Follow redirects inside the proxy instead of returning them.
+    pub follow: bool,
+    pub max: usize,
+    /// Point returned `location` headers back at the proxy.
+    pub rewrite_location: bool,
+    /// The proxy's own address as seen by clients, `https://proxy.example.com`.
+    pub public_url: Option<PublicUrl>,
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct PublicUrl(String);
+
+impl TryFrom<String> for PublicUrl {
+    type Error = String;
+
+    fn try_from(url: String) -> Result<PublicUrl, String> {
+        match Url::parse(&url) {
+            Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {
+                Ok(PublicUrl(url.trim_end_matches('/').to_string()))
+            }
+            _ => Err(format!("Invalid public URL {}", url)),
+        }
+    }
+}
+
+impl Default for RedirectsConfig {
+    fn default() -> RedirectsConfig {
+        RedirectsConfig {
+            follow: false,
+            max: 10,
+            rewrite_location: false,
+            public_url: None,
+        }
+    }
+}
+
+/// One upstream request in a chain of redirects.
+pub struct Hop {
+    pub method: Method,
+    pub target: Target,
+    pub body: Body,
+    /// Whether `body` was sent, as it is taken before sending.
+    pub has_body: bool,
+    pub count: usize,
+    /// Index of the request's API key, whose target restrictions apply to every hop.
+    pub key: Option<usize>,
+    /// Headers revalidating a cached response, only sent on the first hop.
+    pub conditional: HeaderMap,
+}
+
+impl Hop {
+    pub fn new(method: Method, target: Target, body: Body, key: Option<usize>) -> Hop {
+        let has_body = !matches!(body, Body::None | Body::Empty);
+        Hop {
+            method,
+            target,
+            body,
+            has_body,
+            count: 0,
+            key,
+            conditional: HeaderMap::new(),
+        }
+    }
+
+    /**
+     * Credentials are only sent to the origin the client asked for, like
+     * browsers do when following redirects.
+     */
+    pub fn is_same_origin(&self, first: &Uri) -> bool {
+        self.target.uri.scheme_part() == first.scheme_part()
+            && self.target.uri.authority_part() == first.authority_part()
+    }
+}
+
+/**
+ * Breaks with `response` unless it is a redirect the proxy should follow,
+ * in which case the `location` goes through the same checks as the original
+ * target before it is requested.
+ */
+pub fn follow(
+    response: ClientResponse,
+    hop: &Hop,
+    config: &Config,
+) -> impl Future<Item = Loop<(ClientResponse, Uri), Hop>, Error = ProxyError> {
+    let next = if config.redirects.follow {
+        next_request(&response, hop)
+    } else {
+        None
+    };
+    let (method, location) = match next {
+        Some(next) => next,
+        None => {
+            let uri = hop.target.uri.clone();
+            return future::Either::A(future::ok(Loop::Break((response, uri))));
+        }
+    };
+    if hop.count >= config.redirects.max {
+        return future::Either::A(future::failed(ProxyError::TooManyRedirects));
+    }
+
+    let uri = match parse_absolute_uri(&location) {
+        Ok(uri) => uri,
+        Err(err) => return future::Either::A(future::failed(err)),
+    };
+
+    let count = hop.count + 1;
+    let key = hop.key;
+    future::Either::B(check_target(uri, key, config).map(move |target| {
+        Loop::Continue(Hop {
+            method,
+            target,
+            body: Body::Empty,
+            has_body: false,
+            count,
+            key,
+            conditional: HeaderMap::new(),
+        })
+    }))
+}
+
+/**
+ * The method and absolute URL of the request a redirect asks for. 301 and
+ * 302 turn POST into GET and 303 turns everything but HEAD into GET, as
+ * browsers do. A request body cannot be sent twice, so redirects that would
+ * repeat it are returned to the client instead.
+ */
+fn next_request(response: &ClientResponse, hop: &Hop) -> Option<(Method, String)> {
+    let method = match response.status() {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND if hop.method == Method::POST => {
+            Method::GET
+        }
+        StatusCode::SEE_OTHER if hop.method != Method::HEAD => Method::GET,
+        StatusCode::MOVED_PERMANENTLY
+        | StatusCode::FOUND
+        | StatusCode::SEE_OTHER
+        | StatusCode::TEMPORARY_REDIRECT
+        | StatusCode::PERMANENT_REDIRECT => hop.method.clone(),
+        _ => return None,
+    };
+    if method == hop.method && hop.has_body {
+        return None;
+    }
+
+    let location = response.headers().get(header::LOCATION)?.to_str().ok()?;
+    let location = Url::parse(&hop.target.uri.to_string())
+        .ok()?
+        .join(location)
+        .ok()?;
+    Some((method, location.into_string()))
+}
+
+/**
+ * Turns `location` and `content-location` values into proxy URLs, so clients
+ * following them stay behind the proxy. Relative values are resolved against
+ * the upstream URL that answered.
+ */
+pub struct LocationRewrite {
+    upstream: Url,
+    base: String,
+}
+
+impl LocationRewrite {
+    /**
+     * `None` unless `redirects.rewrite_location` is set. Without
+     * `redirects.public_url` the base is taken from the request, honouring
+     * `forwarded` and `x-forwarded-host` headers.
+     */
+    pub fn new(req: &HttpRequest, upstream: &Uri, config: &Config) -> Option<LocationRewrite> {
+        if !config.redirects.rewrite_location {
+            return None;
+        }
+        let base = match &config.redirects.public_url {
+            Some(PublicUrl(url)) => url.clone(),
+            None => {
+                let info = req.connection_info();
+                format!("{}://{}", info.scheme(), info.host())
+            }
+        };
+        Some(LocationRewrite {
+            upstream: Url::parse(&upstream.to_string()).ok()?,
+            base,
+        })
+    }
+
+    /// Values that are not http or https URLs are returned as they are.
+    pub fn apply(&self, value: &HeaderValue) -> HeaderValue {
+        value
+            .to_str()
+            .ok()
+            .and_then(|location| self.upstream.join(location).ok())
+            .filter(|location| location.scheme() == "http" || location.scheme() == "https")
+            .and_then(|location| HeaderValue::from_str(&format!("{}/{}", self.base, location)).ok())
+            .unwrap_or_else(|| value.clone())
+    }
+}
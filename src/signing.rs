@@ -0,0 +1,202 @@
+use crate::auth;
+use crate::config::Config;
+use crate::ProxyError;
+use actix_web::http::Method;
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Query parameter with the hex encoded HMAC-SHA256 of the URL without it.
+pub const SIGNATURE_PARAM: &str = "sig";
+/// Query parameter with the Unix time a signed URL expires at.
+pub const EXPIRES_PARAM: &str = "exp";
+
+/**
+ * With a secret, only URLs signed with it are proxied, or requests with an
+ * API key when `auth.keys` is set.
+ */
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Empty turns signing off.
+    pub secret: String,
+}
+
+/**
+ * Checks the `sig` and `exp` parameters. `Ok(false)` for unsigned requests
+ * that may still authenticate with an API key. The signature does not cover
+ * the method, so signed URLs are only good for GET and HEAD.
+ */
+pub fn verify(req: &HttpRequest, config: &Config) -> Result<bool, ProxyError> {
+    let secret = &config.signing.secret;
+    if secret.is_empty() {
+        return Ok(false);
+    }
+    let signature = param(req, SIGNATURE_PARAM);
+    let expires = param(req, EXPIRES_PARAM);
+    let (signature, expires) = match (signature, expires) {
+        (None, None) if !config.auth.keys.is_empty() => return Ok(false),
+        (None, None) => return Err(ProxyError::Unauthorized),
+        (Some(signature), Some(expires)) => (signature, expires),
+        _ => return Err(ProxyError::InvalidSignature),
+    };
+    if ![Method::GET, Method::HEAD].contains(req.method()) {
+        return Err(ProxyError::InvalidSignature);
+    }
+
+    let signature = hex::decode(signature).map_err(|_| ProxyError::InvalidSignature)?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(signed_url(req, config).as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| ProxyError::InvalidSignature)?;
+
+    let expires = expires
+        .parse::<u64>()
+        .map_err(|_| ProxyError::InvalidSignature)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ProxyError::InternalServerError)?;
+    if now.as_secs() >= expires {
+        return Err(ProxyError::SignatureExpired);
+    }
+    Ok(true)
+}
+
+/// Whether a query string pair is `sig` or `exp`, which are not passed on.
+pub fn is_signing_param(pair: &str, config: &Config) -> bool {
+    !config.signing.secret.is_empty() && [SIGNATURE_PARAM, EXPIRES_PARAM].contains(&name(pair))
+}
+
+/// The target URL the signature covers, `exp` included.
+fn signed_url(req: &HttpRequest, config: &Config) -> String {
+    let query = req
+        .query_string()
+        .split('&')
+        .filter(|pair| name(pair) != SIGNATURE_PARAM && !auth::is_key_param(pair, config))
+        .collect::<Vec<_>>()
+        .join("&");
+    [req.path().get(1..).unwrap_or(""), "?", &query].concat()
+}
+
+fn param<'a>(req: &'a HttpRequest, param: &str) -> Option<&'a str> {
+    req.query_string()
+        .split('&')
+        .find(|pair| name(pair) == param)
+        .map(|pair| pair.split_once('=').map_or("", |(_, value)| value))
+}
+
+fn name(pair: &str) -> &str {
+    pair.split('=').next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const TARGET: &str = "https://api.example.com/image.png?size=large";
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.signing.secret = "secret".to_string();
+        config
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// `TARGET` with `exp` and its signature, as a backend would sign it.
+    fn signed(expires: u64) -> String {
+        let url = format!("{}&exp={}", TARGET, expires);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(url.as_bytes());
+        format!("/{}&sig={}", url, hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn verify_request(method: Method, uri: &str) -> Result<bool, ProxyError> {
+        let req = TestRequest::with_uri(uri).method(method).to_http_request();
+        verify(&req, &config())
+    }
+
+    #[test]
+    fn accepts_signed_urls() {
+        let uri = signed(now() + 60);
+        assert!(matches!(verify_request(Method::GET, &uri), Ok(true)));
+        assert!(matches!(verify_request(Method::HEAD, &uri), Ok(true)));
+    }
+
+    #[test]
+    fn rejects_tampered_urls() {
+        let uri = signed(now() + 60);
+        for tampered in &[
+            uri.replace("large", "small"),
+            uri.replace("api.example.com", "internal.example.com"),
+            format!("{}&extra=1", uri),
+            uri.replacen("&sig=", "&sig=00", 1),
+            uri.replacen("&sig=", "&sig=zz", 1),
+        ] {
+            assert!(
+                matches!(
+                    verify_request(Method::GET, tampered),
+                    Err(ProxyError::InvalidSignature)
+                ),
+                "{}",
+                tampered
+            );
+        }
+        let unexpiring = format!("/{}&sig=00", TARGET);
+        assert!(matches!(
+            verify_request(Method::GET, &unexpiring),
+            Err(ProxyError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_urls() {
+        assert!(matches!(
+            verify_request(Method::GET, &signed(now() - 1)),
+            Err(ProxyError::SignatureExpired)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_methods() {
+        let uri = signed(now() + 60);
+        for method in &[Method::POST, Method::PUT, Method::DELETE, Method::PATCH] {
+            assert!(matches!(
+                verify_request(method.clone(), &uri),
+                Err(ProxyError::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn requires_a_signature() {
+        assert!(matches!(
+            verify_request(Method::GET, &format!("/{}", TARGET)),
+            Err(ProxyError::Unauthorized)
+        ));
+        let req = TestRequest::with_uri(&format!("/{}", TARGET)).to_http_request();
+        assert!(matches!(verify(&req, &Config::default()), Ok(false)));
+    }
+
+    #[test]
+    fn rejects_authority_form_requests() {
+        let req = TestRequest::with_uri("example.com:443")
+            .method(Method::CONNECT)
+            .to_http_request();
+        assert_eq!(req.path(), "");
+        assert_eq!(signed_url(&req, &config()), "?");
+        assert!(matches!(
+            verify(&req, &config()),
+            Err(ProxyError::Unauthorized)
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::targets::{in_network, AddressRules};
+use crate::ProxyError;
+use actix_web::error::BlockingError;
+use actix_web::http::uri::Uri;
+use actix_web::web;
+use futures::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+/**
+ * Ranges the proxy refuses to connect to: loopback, private, link-local
+ * (which includes the 169.254.169.254 metadata service), shared, multicast
+ * and reserved addresses.
+ */
+const BLOCKED_V4: &[([u8; 4], u8)] = &[
+    ([0, 0, 0, 0], 8),
+    ([10, 0, 0, 0], 8),
+    ([100, 64, 0, 0], 10),
+    ([127, 0, 0, 0], 8),
+    ([169, 254, 0, 0], 16),
+    ([172, 16, 0, 0], 12),
+    ([192, 0, 0, 0], 24),
+    ([192, 0, 2, 0], 24),
+    ([192, 168, 0, 0], 16),
+    ([198, 18, 0, 0], 15),
+    ([198, 51, 100, 0], 24),
+    ([203, 0, 113, 0], 24),
+    ([224, 0, 0, 0], 4),
+    ([240, 0, 0, 0], 4),
+];
+
+/// IPv6 counterparts, including unique local `fd00:ec2::254` metadata.
+const BLOCKED_V6: &[([u16; 8], u8)] = &[
+    ([0, 0, 0, 0, 0, 0, 0, 0], 127),
+    ([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32),
+    ([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7),
+    ([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10),
+    ([0xfec0, 0, 0, 0, 0, 0, 0, 0], 10),
+    ([0xff00, 0, 0, 0, 0, 0, 0, 0], 8),
+];
+
+/**
+ * A validated upstream. The client connects to `address` instead of
+ * resolving the host again, so DNS cannot answer with a different, private
+ * address between the check and the request.
+ */
+pub struct Target {
+    pub uri: Uri,
+    pub address: Option<SocketAddr>,
+}
+
+/**
+ * Resolves the host and refuses the target if any of its addresses is
+ * outside the CIDR rules or, unless `allow_private` is set, not public.
+ */
+pub fn resolve(
+    uri: Uri,
+    allow_private: bool,
+    rules: AddressRules,
+) -> impl Future<Item = Target, Error = ProxyError> {
+    let host = uri
+        .host()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = uri.port_u16().unwrap_or_else(|| match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+
+    web::block(move || {
+        (host.as_str(), port)
+            .to_socket_addrs()
+            .map(|addresses| (host, addresses.collect::<Vec<_>>()))
+    })
+    .map_err(|err| match err {
+        BlockingError::Error(error) => ProxyError::DnsFailed(error.to_string()),
+        BlockingError::Canceled => ProxyError::InternalServerError,
+    })
+    .and_then(move |(host, addresses)| {
+        if addresses.iter().any(|address| !rules.allows(address.ip())) {
+            return Err(ProxyError::TargetNotAllowed(host));
+        }
+        match addresses
+            .iter()
+            .find(|address| !allow_private && !is_public(address.ip()))
+        {
+            Some(address) => Err(ProxyError::AddressNotAllowed(host, address.ip())),
+            None if addresses.is_empty() => Err(ProxyError::DnsFailed(format!(
+                "No addresses found for {}",
+                host
+            ))),
+            None => Ok(Target {
+                uri,
+                address: addresses.first().cloned(),
+            }),
+        }
+    })
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !BLOCKED_V4.iter().any(|(network, prefix)| {
+            in_network(ip.into(), Ipv4Addr::from(*network).into(), *prefix)
+        }),
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(ip.into()),
+            None => !BLOCKED_V6.iter().any(|(network, prefix)| {
+                in_network(ip.into(), Ipv6Addr::from(*network).into(), *prefix)
+            }),
+        },
+    }
+}
+
+/// IPv4-mapped `::ffff:a.b.c.d`, IPv4-compatible `::a.b.c.d` and NAT64
+/// `64:ff9b::a.b.c.d` addresses reach the embedded IPv4 address.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _]
+        | [0x64, 0xff9b, 0, 0, 0, 0, _, _]
+        | [0, 0, 0, 0, 0, 0, 1..=0xffff, _] => {
+            let octets = ip.octets();
+            Some(Ipv4Addr::new(
+                octets[12], octets[13], octets[14], octets[15],
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn blocks_private_ipv4() {
+        for ip in &[
+            "0.0.0.0",
+            "10.1.2.3",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.168.1.1",
+            "198.18.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+        for ip in &["8.8.8.8", "1.1.1.1", "172.32.0.1", "100.128.0.1"] {
+            assert!(public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn blocks_private_ipv6() {
+        for ip in &[
+            "::",
+            "::1",
+            "fc00::1",
+            "fd00:ec2::254",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+        assert!(public("2606:4700::1111"));
+    }
+
+    #[test]
+    fn checks_embedded_ipv4() {
+        let embedded = |ip: &str| embedded_ipv4(ip.parse().unwrap());
+        let localhost = Some(Ipv4Addr::LOCALHOST);
+        assert_eq!(embedded("::ffff:127.0.0.1"), localhost);
+        assert_eq!(embedded("64:ff9b::127.0.0.1"), localhost);
+        assert_eq!(embedded("::127.0.0.1"), localhost);
+        assert_eq!(embedded("::1"), None);
+        assert_eq!(embedded("2606:4700::1111"), None);
+
+        assert!(!public("::ffff:169.254.169.254"));
+        assert!(!public("64:ff9b::10.0.0.1"));
+        assert!(public("::ffff:8.8.8.8"));
+    }
+}
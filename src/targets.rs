@@ -0,0 +1,283 @@
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/**
+ * Upstream hosts the proxy may be pointed at. An empty allowlist allows every
+ * host not on the denylist.
+ */
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TargetRules {
+    allow: Vec<HostPattern>,
+    deny: Vec<HostPattern>,
+    /// Skips the public address checks in `ssrf`, not the CIDR rules.
+    pub allow_private: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub enum HostPattern {
+    /// `api.example.com`
+    Exact(String),
+    /// `.example.com`, matches example.com and all its subdomains
+    Suffix(String),
+    /// `api-*.example.com`, `*` matches any characters and `?` a single one
+    Glob(String),
+    /// `10.0.0.0/8` or `2001:db8::/32`, matches IP literals and, through
+    /// `AddressRules`, the addresses hosts resolve to
+    Cidr(IpAddr, u8),
+}
+
+/**
+ * The CIDR rules for the addresses a host resolves to, checked by
+ * `ssrf::resolve`. Resolvers accept more than `IpAddr` parses, like
+ * `2130706433` for 127.0.0.1, so literals alone cannot be trusted.
+ */
+pub struct AddressRules {
+    deny: Vec<(IpAddr, u8)>,
+    /// `None` when the host is allowed by name.
+    allow: Option<Vec<(IpAddr, u8)>>,
+}
+
+impl TargetRules {
+    /**
+     * The denylist wins over the allowlist, so `.example.com` can be allowed
+     * while `admin.example.com` is denied.
+     */
+    pub fn is_allowed(&self, host: &str) -> bool {
+        !host_matches(&self.deny, host)
+            && (self.allow.is_empty()
+                || host_matches(&self.allow, host)
+                || !networks(&self.allow).is_empty())
+    }
+
+    /// Hosts not allowed by name have to resolve into an allowed network.
+    pub fn address_rules(&self, host: &str) -> AddressRules {
+        let allow = if self.allow.is_empty() || host_matches(&self.allow, host) {
+            None
+        } else {
+            Some(networks(&self.allow))
+        };
+        AddressRules {
+            deny: networks(&self.deny),
+            allow,
+        }
+    }
+}
+
+impl AddressRules {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let in_any = |networks: &[(IpAddr, u8)]| {
+            networks
+                .iter()
+                .any(|(network, prefix)| in_network(ip, *network, *prefix))
+        };
+        !in_any(&self.deny) && self.allow.as_deref().is_none_or(in_any)
+    }
+}
+
+/// `admin.example.com.` is the same host as `admin.example.com`.
+pub fn host_matches(patterns: &[HostPattern], host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_lowercase();
+    patterns.iter().any(|pattern| pattern.matches(&host))
+}
+
+fn networks(patterns: &[HostPattern]) -> Vec<(IpAddr, u8)> {
+    patterns
+        .iter()
+        .filter_map(|pattern| match pattern {
+            HostPattern::Cidr(network, prefix) => Some((*network, *prefix)),
+            _ => None,
+        })
+        .collect()
+}
+
+impl HostPattern {
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(exact) => host == exact,
+            HostPattern::Suffix(suffix) => host.ends_with(suffix.as_str()) || host == &suffix[1..],
+            HostPattern::Glob(glob) => glob_matches(glob.as_bytes(), host.as_bytes()),
+            HostPattern::Cidr(network, prefix) => match host.parse::<IpAddr>() {
+                Ok(ip) => in_network(ip.to_canonical(), *network, *prefix),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<HostPattern, String> {
+        let pattern = pattern.to_lowercase();
+        if let Ok(ip) = pattern.parse::<IpAddr>() {
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            return Ok(HostPattern::Cidr(ip, prefix));
+        }
+        if let Some(slash) = pattern.find('/') {
+            return parse_cidr(&pattern[..slash], &pattern[slash + 1..])
+                .ok_or_else(|| format!("Invalid CIDR {}", pattern));
+        }
+
+        if pattern.contains(&['*', '?'][..]) {
+            Ok(HostPattern::Glob(pattern))
+        } else if pattern.len() > 1 && pattern.starts_with('.') {
+            Ok(HostPattern::Suffix(pattern))
+        } else if !pattern.is_empty() && !pattern.contains(&[':', '['][..]) {
+            Ok(HostPattern::Exact(pattern))
+        } else {
+            Err(format!("Invalid host pattern {}", pattern))
+        }
+    }
+}
+
+impl TryFrom<String> for HostPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<HostPattern, String> {
+        pattern.parse()
+    }
+}
+
+fn parse_cidr(network: &str, prefix: &str) -> Option<HostPattern> {
+    let network = network.parse::<IpAddr>().ok()?;
+    let prefix = prefix.parse::<u8>().ok()?;
+    let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+    if prefix > max_prefix {
+        return None;
+    }
+    Some(HostPattern::Cidr(network, prefix))
+}
+
+pub fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+fn glob_matches(glob: &[u8], host: &[u8]) -> bool {
+    match (glob.first(), host.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_matches(&glob[1..], host) || (!host.is_empty() && glob_matches(glob, &host[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_matches(&glob[1..], &host[1..]),
+        (Some(g), Some(h)) if g == h => glob_matches(&glob[1..], &host[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> TargetRules {
+        let patterns = |list: &[&str]| list.iter().map(|p| p.parse().unwrap()).collect();
+        TargetRules {
+            allow: patterns(allow),
+            deny: patterns(deny),
+            allow_private: false,
+        }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert!(
+            matches!("API.example.com".parse(), Ok(HostPattern::Exact(h)) if h == "api.example.com")
+        );
+        assert!(matches!(".example.com".parse(), Ok(HostPattern::Suffix(_))));
+        assert!(matches!(
+            "api-*.example.com".parse(),
+            Ok(HostPattern::Glob(_))
+        ));
+        assert!(matches!("10.0.0.1".parse(), Ok(HostPattern::Cidr(_, 32))));
+        assert!(matches!(
+            "2001:db8::/32".parse(),
+            Ok(HostPattern::Cidr(_, 32))
+        ));
+        assert!("10.0.0.0/33".parse::<HostPattern>().is_err());
+        assert!("example.com:80".parse::<HostPattern>().is_err());
+        assert!("".parse::<HostPattern>().is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches(b"api-*.example.com", b"api-v2.example.com"));
+        assert!(glob_matches(b"api-*.example.com", b"api-.example.com"));
+        assert!(glob_matches(b"api-?.example.com", b"api-1.example.com"));
+        assert!(!glob_matches(b"api-?.example.com", b"api-12.example.com"));
+        assert!(!glob_matches(b"api-*.example.com", b"api-v2.example.org"));
+        assert!(glob_matches(b"*", b""));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(in_network(ip("10.1.2.3"), ip("10.0.0.0"), 8));
+        assert!(!in_network(ip("11.0.0.1"), ip("10.0.0.0"), 8));
+        assert!(in_network(ip("1.2.3.4"), ip("0.0.0.0"), 0));
+        assert!(in_network(ip("2001:db8::1"), ip("2001:db8::"), 32));
+        assert!(!in_network(ip("2001:db9::1"), ip("2001:db8::"), 32));
+        assert!(!in_network(ip("10.0.0.1"), ip("::"), 0));
+    }
+
+    #[test]
+    fn denylist_wins() {
+        let rules = rules(&[".example.com"], &["admin.example.com"]);
+        assert!(rules.is_allowed("api.example.com"));
+        assert!(rules.is_allowed("example.com"));
+        assert!(!rules.is_allowed("admin.example.com"));
+        assert!(!rules.is_allowed("example.org"));
+    }
+
+    #[test]
+    fn trailing_dot_does_not_bypass_rules() {
+        let rules = rules(&[], &["admin.example.com", ".internal"]);
+        assert!(!rules.is_allowed("admin.example.com."));
+        assert!(!rules.is_allowed("ADMIN.example.com."));
+        assert!(!rules.is_allowed("db.internal."));
+    }
+
+    #[test]
+    fn denied_ranges_apply_to_resolved_addresses() {
+        let rules = rules(&[], &["8.8.8.0/24"]);
+        // `134744072` resolves to 8.8.8.8 but is not an `IpAddr` literal.
+        assert!(rules.is_allowed("134744072"));
+        let addresses = rules.address_rules("134744072");
+        assert!(!addresses.allows(ip("8.8.8.8")));
+        assert!(!addresses.allows(ip("::ffff:8.8.8.8")));
+        assert!(addresses.allows(ip("8.8.4.4")));
+    }
+
+    #[test]
+    fn allowed_ranges_apply_to_hosts_not_allowed_by_name() {
+        let mixed = rules(&["api.example.com", "10.0.0.0/8"], &[]);
+        assert!(mixed.address_rules("api.example.com").allows(ip("1.2.3.4")));
+        assert!(mixed.is_allowed("internal.example.org"));
+        let addresses = mixed.address_rules("internal.example.org");
+        assert!(addresses.allows(ip("10.1.2.3")));
+        assert!(!addresses.allows(ip("1.2.3.4")));
+
+        let names_only = rules(&["api.example.com"], &[]);
+        assert!(!names_only.is_allowed("other.example.com"));
+    }
+}
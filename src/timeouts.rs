@@ -0,0 +1,46 @@
+use crate::config::Config;
+use crate::ProxyError;
+use actix_web::HttpRequest;
+use futures::Future;
+use std::time::Duration;
+use tokio_timer::Timeout;
+
+/// Lets a client ask for a shorter total timeout, in seconds.
+pub const OVERRIDE_HEADER: &str = "x-proxy-timeout";
+
+/**
+ * The time a request may take from resolving the target until the upstream
+ * response headers arrive, redirects included. `x-proxy-timeout` can lower
+ * it but not raise it above `timeouts.total`.
+ */
+pub fn total(req: &HttpRequest, config: &Config) -> Result<Duration, ProxyError> {
+    let seconds = match req.headers().get(OVERRIDE_HEADER) {
+        Some(value) => match value.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+            Some(seconds) if seconds > 0 => seconds.min(config.timeouts.total),
+            _ => {
+                return Err(ProxyError::RequestError(format!(
+                    "{} must be a number of seconds",
+                    OVERRIDE_HEADER
+                )))
+            }
+        },
+        None => config.timeouts.total,
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+pub fn with_timeout<F>(
+    future: F,
+    timeout: Duration,
+) -> impl Future<Item = F::Item, Error = ProxyError>
+where
+    F: Future<Error = ProxyError>,
+{
+    Timeout::new(future, timeout).map_err(|err| {
+        if err.is_elapsed() {
+            ProxyError::Timeout
+        } else {
+            err.into_inner().unwrap_or(ProxyError::InternalServerError)
+        }
+    })
+}